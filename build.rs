@@ -12,4 +12,13 @@ fn main() {
     } else if target.starts_with("arm") {
         println!("cargo:rustc-cfg=arm")
     }
+
+    if ["thumbv6m", "thumbv7m", "thumbv7em", "thumbv8m"].iter().any(|arch| target.starts_with(arch)) {
+        println!("cargo:rustc-cfg=cortex_m");
+
+        // baseline profiles lack the configurable fault status registers
+        if target.starts_with("thumbv6m") || target.starts_with("thumbv8m.base") {
+            println!("cargo:rustc-cfg=cortex_m_base")
+        }
+    }
 }
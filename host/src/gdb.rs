@@ -0,0 +1,487 @@
+//! Forwarding semihosting file operations as GDB File-I/O requests
+//!
+//! GDB stubs can offer semihosting by handing each trap to [`FileIo::start`],
+//! sending the resulting `F` packet to GDB, and passing its reply back to
+//! [`FileIo::reply`] until the call is done. GDB accesses target memory for
+//! buffers and paths itself, with `m` and `M` packets.
+
+use std::convert::TryFrom;
+use semihosting::{Exception, Mode, Syscall};
+use crate::memory::{Memory, MemoryError, Target};
+use crate::server::{Response, SemihostingServer};
+
+// File-I/O protocol values, independent of the host GDB runs on
+pub const O_RDONLY: u32 = 0x0;
+pub const O_WRONLY: u32 = 0x1;
+pub const O_RDWR: u32 = 0x2;
+pub const O_APPEND: u32 = 0x8;
+pub const O_CREAT: u32 = 0x200;
+pub const O_TRUNC: u32 = 0x400;
+
+/// `S_IRUSR | S_IWUSR | S_IRGRP | S_IROTH`
+const CREATE_MODE: u32 = 0o644;
+
+const SEEK_SET: u32 = 0;
+const SEEK_CUR: u32 = 1;
+const SEEK_END: u32 = 2;
+
+const EBADF: i32 = 9;
+const EFAULT: i32 = 14;
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+
+/// Maps a semihosting `fopen` mode onto File-I/O `open` flags
+pub fn open_flags(mode: Mode) -> u32 {
+    let update = mode.contains(Mode::MODE_UPDATE);
+    let access = if update { O_RDWR } else { O_WRONLY };
+    if mode.contains(Mode::MODE_APPEND) {
+        access | O_CREAT | O_APPEND
+    } else if mode.contains(Mode::MODE_READ_WRITE) {
+        access | O_CREAT | O_TRUNC
+    } else if update {
+        O_RDWR
+    } else {
+        O_RDONLY
+    }
+}
+
+#[test]
+fn mode_flags() {
+    assert_eq!(open_flags(Mode::MODE_READ_ONLY | Mode::BINARY), O_RDONLY);
+    assert_eq!(open_flags(Mode::MODE_READ_ONLY | Mode::MODE_UPDATE), O_RDWR);
+    assert_eq!(open_flags(Mode::MODE_READ_WRITE), O_WRONLY | O_CREAT | O_TRUNC);
+    assert_eq!(open_flags(Mode::MODE_READ_WRITE | Mode::MODE_UPDATE | Mode::BINARY), O_RDWR | O_CREAT | O_TRUNC);
+    assert_eq!(open_flags(Mode::MODE_APPEND), O_WRONLY | O_CREAT | O_APPEND);
+    assert_eq!(open_flags(Mode::MODE_APPEND | Mode::MODE_UPDATE), O_RDWR | O_CREAT | O_APPEND);
+}
+
+/// Semihosting handles are never zero, so they are offset from GDB's descriptors
+fn gdb_fd(fd: u64) -> Result<u64, i32> {
+    fd.checked_sub(1).ok_or(EBADF)
+}
+
+/// What the stub should do next
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Step {
+    /// Send this packet to GDB and pass its reply to [`FileIo::reply`]
+    Request(String),
+    /// The call is complete
+    Done(Response),
+}
+
+/// How to interpret GDB's reply to the request in flight
+#[derive(Copy, Clone, Debug)]
+enum Pending {
+    /// Zero on success
+    Status,
+    Value,
+    Open,
+    /// The number of bytes not transferred
+    Transfer { len: u64 },
+    /// `SYS_WRITEC` and `SYS_WRITE0` don't return anything
+    Console,
+    FLenCurrent { fd: u64 },
+    FLenEnd { fd: u64, current: u64 },
+    FLenRestore { len: u64 },
+}
+
+/// A GDB `F` reply
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Reply {
+    pub result: i64,
+    pub errno: Option<i32>,
+    /// The user interrupted the call with Ctrl-C
+    pub interrupted: bool,
+}
+
+impl Reply {
+    /// Parses `Fretcode[,errno[,C]][;attachment]`
+    pub fn parse(packet: &str) -> Option<Self> {
+        let packet = packet.strip_prefix('F')?;
+        let packet = packet.split(';').next()?;
+        let mut fields = packet.split(',');
+        let result = match fields.next()? {
+            result if result.starts_with('-') => -i64::from_str_radix(&result[1..], 16).ok()?,
+            result => i64::from_str_radix(result, 16).ok()?,
+        };
+        let errno = match fields.next() {
+            Some(errno) => Some(i32::from_str_radix(errno, 16).ok()?),
+            None => None,
+        };
+        let interrupted = fields.next() == Some("C");
+        Some(Self {
+            result,
+            errno,
+            interrupted,
+        })
+    }
+}
+
+#[test]
+fn reply_parse() {
+    assert_eq!(Reply::parse("F1a"), Some(Reply { result: 0x1a, errno: None, interrupted: false }));
+    assert_eq!(Reply::parse("F-1,9"), Some(Reply { result: -1, errno: Some(9), interrupted: false }));
+    assert_eq!(Reply::parse("F-1,4,C"), Some(Reply { result: -1, errno: Some(4), interrupted: true }));
+    assert_eq!(Reply::parse("F0;attachment"), Some(Reply { result: 0, errno: None, interrupted: false }));
+    assert_eq!(Reply::parse("OK"), None);
+}
+
+/// Translates semihosting calls into GDB File-I/O requests and back
+///
+/// Operations with no File-I/O equivalent, such as the clocks, command line
+/// and exceptions, are serviced locally by a [`SemihostingServer`].
+/// `SYS_READC` is unsupported, as GDB would need a target buffer to read into.
+pub struct FileIo {
+    local: SemihostingServer,
+    errno: i32,
+    pending: Option<Pending>,
+}
+
+impl FileIo {
+    pub fn new(target: Target) -> Self {
+        Self {
+            local: SemihostingServer::new(target),
+            errno: 0,
+            pending: None,
+        }
+    }
+
+    /// The server used for operations that don't involve GDB
+    pub fn local_mut(&mut self) -> &mut SemihostingServer {
+        &mut self.local
+    }
+
+    fn request(&mut self, pending: Pending, packet: String) -> Step {
+        self.pending = Some(pending);
+        Step::Request(packet)
+    }
+
+    fn fail(&mut self, errno: i32) -> Step {
+        self.errno = errno;
+        self.pending = None;
+        Step::Done(Response::Return(self.local.target().word(-1)))
+    }
+
+    fn done(&mut self, value: i64) -> Step {
+        self.pending = None;
+        Step::Done(Response::Return(self.local.target().word(value)))
+    }
+
+    /// Starts servicing a call, given the operation number and parameter register
+    pub fn start(&mut self, operation: u64, parameter: u64, memory: &mut dyn Memory) -> Step {
+        let syscall = match usize::try_from(operation).ok().and_then(|op| Syscall::try_from(op).ok()) {
+            Some(syscall) => syscall,
+            None => return self.fail(ENOSYS),
+        };
+        match self.translate(syscall, parameter, memory) {
+            Ok(step) => step,
+            Err(Error::Memory(_)) => self.fail(EFAULT),
+            Err(Error::Errno(errno)) => self.fail(errno),
+        }
+    }
+
+    fn translate(&mut self, syscall: Syscall, parameter: u64, memory: &mut dyn Memory) -> Result<Step, Error> {
+        let target = self.local.target();
+        Ok(match syscall {
+            Syscall::Open => {
+                let [name, mode, len] = target.read_block(memory, parameter)?;
+                if mode > 11 {
+                    return Err(Error::Errno(EINVAL))
+                }
+                let mode = Mode::from_bits_truncate(mode as u32);
+                if target.read_bytes(memory, name, len)? == b":tt" {
+                    let fd = match mode {
+                        mode if mode.contains(Mode::MODE_APPEND) => 2,
+                        mode if mode.contains(Mode::MODE_READ_WRITE) => 1,
+                        _ => 0,
+                    };
+                    return Ok(self.done(fd + 1))
+                }
+                // the length given to GDB includes the nul
+                self.request(Pending::Open, format!("Fopen,{:x}/{:x},{:x},{:x}", name, len + 1, open_flags(mode), CREATE_MODE))
+            },
+            Syscall::Close => {
+                let [fd] = target.read_block(memory, parameter)?;
+                match gdb_fd(fd)? {
+                    // GDB's console stays open
+                    0..=2 => self.done(0),
+                    fd => self.request(Pending::Status, format!("Fclose,{:x}", fd)),
+                }
+            },
+            Syscall::WriteC => self.request(Pending::Console, format!("Fwrite,1,{:x},1", parameter)),
+            Syscall::Write0 => match target.read_cstr(memory, parameter)?.len() {
+                0 => self.done(0),
+                len => self.request(Pending::Console, format!("Fwrite,1,{:x},{:x}", parameter, len)),
+            },
+            Syscall::Write => {
+                let [fd, address, len] = target.read_block(memory, parameter)?;
+                self.request(Pending::Transfer { len }, format!("Fwrite,{:x},{:x},{:x}", gdb_fd(fd)?, address, len))
+            },
+            Syscall::Read => {
+                let [fd, address, len] = target.read_block(memory, parameter)?;
+                self.request(Pending::Transfer { len }, format!("Fread,{:x},{:x},{:x}", gdb_fd(fd)?, address, len))
+            },
+            Syscall::IsTTY => {
+                let [fd] = target.read_block(memory, parameter)?;
+                self.request(Pending::Value, format!("Fisatty,{:x}", gdb_fd(fd)?))
+            },
+            Syscall::Seek => {
+                let [fd, position] = target.read_block(memory, parameter)?;
+                self.request(Pending::Status, format!("Flseek,{:x},{:x},{:x}", gdb_fd(fd)?, position, SEEK_SET))
+            },
+            Syscall::FLen => {
+                // find the end by seeking there and back
+                let [fd] = target.read_block(memory, parameter)?;
+                let fd = gdb_fd(fd)?;
+                self.request(Pending::FLenCurrent { fd }, format!("Flseek,{:x},0,{:x}", fd, SEEK_CUR))
+            },
+            Syscall::Remove => {
+                let [name, len] = target.read_block(memory, parameter)?;
+                self.request(Pending::Status, format!("Funlink,{:x}/{:x}", name, len + 1))
+            },
+            Syscall::Rename => {
+                let [from, from_len, to, to_len] = target.read_block(memory, parameter)?;
+                self.request(Pending::Status, format!("Frename,{:x}/{:x},{:x}/{:x}", from, from_len + 1, to, to_len + 1))
+            },
+            Syscall::System => {
+                let [command, len] = target.read_block(memory, parameter)?;
+                self.request(Pending::Value, format!("Fsystem,{:x}/{:x}", command, len + 1))
+            },
+            Syscall::Errno => self.done(self.errno as i64),
+            Syscall::ReadC => return Err(Error::Errno(ENOSYS)),
+            Syscall::Clock | Syscall::Time | Syscall::TmpNam | Syscall::GetCmdline | Syscall::HeapInfo
+                | Syscall::IsError | Syscall::EnterSVC | Syscall::ReportException
+                | Syscall::ReportExceptionExtended | Syscall::Elapsed | Syscall::TickFreq => {
+                let response = self.local.handle(usize::from(syscall) as u64, parameter, memory);
+                if response == Response::Return(target.word(-1)) {
+                    self.errno = self.local.errno();
+                }
+                self.pending = None;
+                Step::Done(response)
+            },
+        })
+    }
+
+    /// Continues the call in flight with GDB's reply packet
+    pub fn reply(&mut self, packet: &str) -> Step {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return self.fail(EINVAL),
+        };
+        let reply = match Reply::parse(packet) {
+            Some(reply) => reply,
+            None => return self.fail(EINVAL),
+        };
+        if reply.interrupted {
+            return Step::Done(Response::Exception {
+                reason: usize::from(Exception::UserInterruption) as u64,
+                subcode: None,
+            })
+        }
+        if reply.result < 0 {
+            self.errno = reply.errno.unwrap_or(EINVAL);
+            return match pending {
+                Pending::Transfer { len } => self.done(len as i64),
+                _ => self.fail(self.errno),
+            }
+        }
+
+        let result = reply.result;
+        match pending {
+            Pending::Status | Pending::Console => self.done(0),
+            Pending::Value => self.done(result),
+            Pending::Open => self.done(result + 1),
+            Pending::Transfer { len } => self.done(len.saturating_sub(result as u64) as i64),
+            Pending::FLenCurrent { fd } => self.request(Pending::FLenEnd { fd, current: result as u64 }, format!("Flseek,{:x},0,{:x}", fd, SEEK_END)),
+            Pending::FLenEnd { fd, current } => self.request(Pending::FLenRestore { len: result as u64 }, format!("Flseek,{:x},{:x},{:x}", fd, current, SEEK_SET)),
+            Pending::FLenRestore { len } => self.done(len as i64),
+        }
+    }
+}
+
+enum Error {
+    Memory(MemoryError),
+    Errno(i32),
+}
+
+impl From<MemoryError> for Error {
+    fn from(e: MemoryError) -> Self {
+        Error::Memory(e)
+    }
+}
+
+impl From<i32> for Error {
+    fn from(errno: i32) -> Self {
+        Error::Errno(errno)
+    }
+}
+
+/// Plays GDB's part against an in-memory file system
+#[cfg(test)]
+#[derive(Default)]
+struct Loopback {
+    files: std::collections::BTreeMap<String, Vec<u8>>,
+    open: std::collections::BTreeMap<u64, (String, u64)>,
+}
+
+#[cfg(test)]
+impl Loopback {
+    fn path(memory: &mut crate::memory::VecMemory, field: &str) -> String {
+        let (address, len) = field.split_at(field.find('/').unwrap());
+        let mut bytes = vec![0; usize::from_str_radix(&len[1..], 16).unwrap()];
+        memory.read(u64::from_str_radix(address, 16).unwrap(), &mut bytes).unwrap();
+        assert_eq!(bytes.pop(), Some(0));
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn serve(&mut self, packet: &str, memory: &mut crate::memory::VecMemory) -> String {
+        let mut fields = packet.strip_prefix('F').unwrap().split(',');
+        let call = fields.next().unwrap();
+        let fields: Vec<&str> = fields.collect();
+        let num = |i: usize| u64::from_str_radix(fields[i], 16).unwrap();
+        match call {
+            "open" => {
+                let path = Self::path(memory, fields[0]);
+                let flags = num(1) as u32;
+                if flags & O_CREAT == 0 && !self.files.contains_key(&path) {
+                    return "F-1,2".into()
+                }
+                let file = self.files.entry(path.clone()).or_default();
+                if flags & O_TRUNC != 0 {
+                    file.clear();
+                }
+                let fd = (3..).find(|fd| !self.open.contains_key(fd)).unwrap();
+                self.open.insert(fd, (path, 0));
+                format!("F{:x}", fd)
+            },
+            "close" => match self.open.remove(&num(0)) {
+                Some(_) => "F0".into(),
+                None => "F-1,9".into(),
+            },
+            "write" | "read" => {
+                let (path, position) = match self.open.get_mut(&num(0)) {
+                    Some(open) => open,
+                    None => return "F-1,9".into(),
+                };
+                let file = self.files.get_mut(path).unwrap();
+                let (address, len) = (num(1), num(2) as usize);
+                let start = *position as usize;
+                let len = if call == "write" {
+                    let mut data = vec![0; len];
+                    memory.read(address, &mut data).unwrap();
+                    file.resize(file.len().max(start + len), 0);
+                    file[start..start + len].copy_from_slice(&data);
+                    len
+                } else {
+                    let len = len.min(file.len().saturating_sub(start));
+                    memory.write(address, &file[start..start + len]).unwrap();
+                    len
+                };
+                *position += len as u64;
+                format!("F{:x}", len)
+            },
+            "lseek" => {
+                let (path, position) = match self.open.get_mut(&num(0)) {
+                    Some(open) => open,
+                    None => return "F-1,9".into(),
+                };
+                *position = match num(2) as u32 {
+                    SEEK_SET => num(1),
+                    SEEK_CUR => *position + num(1),
+                    _ => self.files[&*path].len() as u64 + num(1),
+                };
+                format!("F{:x}", position)
+            },
+            "isatty" => format!("F{:x}", (num(0) <= 2) as u8),
+            "unlink" => match self.files.remove(&Self::path(memory, fields[0])) {
+                Some(_) => "F0".into(),
+                None => "F-1,2".into(),
+            },
+            _ => "F-1,58".into(),
+        }
+    }
+
+    fn call(&mut self, io: &mut FileIo, memory: &mut crate::memory::VecMemory, operation: Syscall, parameter: u64) -> Response {
+        let mut step = io.start(usize::from(operation) as u64, parameter, memory);
+        loop {
+            match step {
+                Step::Done(response) => return response,
+                Step::Request(packet) => {
+                    let reply = self.serve(&packet, memory);
+                    step = io.reply(&reply);
+                },
+            }
+        }
+    }
+}
+
+#[test]
+fn loopback_files() {
+    let mut memory = crate::memory::VecMemory {
+        base: 0x1000,
+        bytes: vec![0; 0x100],
+    };
+    let target = Target::ARM32;
+    let words = |memory: &mut crate::memory::VecMemory, address: u64, words: &[u64]| {
+        for (i, &word) in words.iter().enumerate() {
+            target.write_word(memory, address + 4 * i as u64, word).unwrap();
+        }
+        address
+    };
+    memory.write(0x1000, b"log.txt\0hello\0:tt\0").unwrap();
+    let (path, data, tt, buffer) = (0x1000, 0x1008, 0x100e, 0x1080);
+
+    let mut io = FileIo::new(target);
+    let mut gdb = Loopback::default();
+
+    let open = words(&mut memory, 0x1040, &[path, (Mode::MODE_READ_WRITE | Mode::MODE_UPDATE).bits() as u64, 7]);
+    let fd = match gdb.call(&mut io, &mut memory, Syscall::Open, open) {
+        Response::Return(fd) => fd,
+        response => panic!("{:?}", response),
+    };
+    assert_eq!(fd, 4);
+
+    let write = words(&mut memory, 0x1040, &[fd, data, 5]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Write, write), Response::Return(0));
+    let flen = words(&mut memory, 0x1040, &[fd]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::FLen, flen), Response::Return(5));
+    let seek = words(&mut memory, 0x1040, &[fd, 1]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Seek, seek), Response::Return(0));
+    let read = words(&mut memory, 0x1040, &[fd, buffer, 8]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Read, read), Response::Return(4));
+    assert_eq!(&memory.bytes[0x80..0x84], b"ello");
+    let close = words(&mut memory, 0x1040, &[fd]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Close, close), Response::Return(0));
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Close, close), Response::Return(u32::MAX as u64));
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Errno, 0), Response::Return(EBADF as u64));
+
+    let open = words(&mut memory, 0x1040, &[tt, Mode::MODE_APPEND.bits() as u64, 3]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Open, open), Response::Return(3));
+    let isatty = words(&mut memory, 0x1040, &[3]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::IsTTY, isatty), Response::Return(1));
+
+    let remove = words(&mut memory, 0x1040, &[path, 7]);
+    assert_eq!(gdb.call(&mut io, &mut memory, Syscall::Remove, remove), Response::Return(0));
+    assert!(gdb.files.is_empty());
+}
+
+#[test]
+fn requests() {
+    let mut memory = crate::memory::VecMemory {
+        base: 0x1000,
+        bytes: vec![0; 0x40],
+    };
+    memory.write(0x1000, b"a.bin\0").unwrap();
+    Target::ARM32.write_word(&mut memory, 0x1010, 0x1000).unwrap();
+    Target::ARM32.write_word(&mut memory, 0x1014, (Mode::MODE_APPEND | Mode::BINARY).bits() as u64).unwrap();
+    Target::ARM32.write_word(&mut memory, 0x1018, 5).unwrap();
+
+    let mut io = FileIo::new(Target::ARM32);
+    assert_eq!(io.start(1, 0x1010, &mut memory), Step::Request("Fopen,1000/6,209,1a4".into()));
+    assert_eq!(io.reply("F-1,d"), Step::Done(Response::Return(u32::MAX as u64)));
+    assert_eq!(io.errno, 13);
+    assert_eq!(io.start(4, 0x1000, &mut memory), Step::Request("Fwrite,1,1000,5".into()));
+    assert_eq!(io.reply("F5"), Step::Done(Response::Return(0)));
+    assert_eq!(io.start(4, 0x1000, &mut memory), Step::Request("Fwrite,1,1000,5".into()));
+    assert_eq!(io.reply("F-1,4,C"), Step::Done(Response::Exception { reason: 0x20025, subcode: None }));
+}
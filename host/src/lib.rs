@@ -0,0 +1,21 @@
+//! Host-side implementation of the semihosting operations
+//!
+//! For emulators, probes and debug stubs that need to service semihosting
+//! traps from a target. The operation numbers, open modes and exception
+//! reasons are shared with the target-side `semihosting` crate.
+//!
+//! ```ignore
+//! let mut server = SemihostingServer::new(Target::ARM32);
+//! // when the target traps with a semihosting call:
+//! match server.handle(cpu.r0(), cpu.r1(), &mut cpu.memory()) {
+//!     Response::Return(value) => cpu.set_r0(value),
+//!     Response::Exception { reason, subcode } => return exit(reason, subcode),
+//! }
+//! ```
+
+pub mod gdb;
+mod memory;
+mod server;
+
+pub use crate::memory::{Memory, MemoryError, Target, VecMemory};
+pub use crate::server::{Response, SemihostingServer, TICK_FREQ};
@@ -0,0 +1,159 @@
+use std::convert::TryInto;
+use std::fmt;
+
+/// Access to the target's address space
+///
+/// Implemented by the emulator or probe servicing semihosting calls.
+pub trait Memory {
+    fn read(&mut self, address: u64, buffer: &mut [u8]) -> Result<(), MemoryError>;
+    fn write(&mut self, address: u64, data: &[u8]) -> Result<(), MemoryError>;
+}
+
+/// The target couldn't access `address`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemoryError {
+    pub address: u64,
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "target memory at {:#x} is inaccessible", self.address)
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+/// A flat memory starting at `base`, mostly useful for tests
+#[derive(Clone, Debug, Default)]
+pub struct VecMemory {
+    pub base: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl VecMemory {
+    fn range(&self, address: u64, len: usize) -> Result<std::ops::Range<usize>, MemoryError> {
+        let error = MemoryError { address };
+        let start: usize = address.checked_sub(self.base).ok_or(error)?.try_into().map_err(|_| error)?;
+        let end = start.checked_add(len).ok_or(error)?;
+        match end <= self.bytes.len() {
+            true => Ok(start..end),
+            false => Err(error),
+        }
+    }
+}
+
+impl Memory for VecMemory {
+    fn read(&mut self, address: u64, buffer: &mut [u8]) -> Result<(), MemoryError> {
+        let range = self.range(address, buffer.len())?;
+        buffer.copy_from_slice(&self.bytes[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, address: u64, data: &[u8]) -> Result<(), MemoryError> {
+        let range = self.range(address, data.len())?;
+        self.bytes[range].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Word size and byte order of the target
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Target {
+    /// 4 on AArch32 and RISC-V 32, 8 on AArch64 and RISC-V 64
+    pub word_size: usize,
+    pub big_endian: bool,
+}
+
+impl Target {
+    pub const ARM32: Target = Target {
+        word_size: 4,
+        big_endian: false,
+    };
+    pub const AARCH64: Target = Target {
+        word_size: 8,
+        big_endian: false,
+    };
+
+    /// Sign-extends a word, for return values such as `-1`
+    pub fn signed(&self, word: u64) -> i64 {
+        match self.word_size {
+            4 => word as u32 as i32 as i64,
+            _ => word as i64,
+        }
+    }
+
+    /// Truncates a value to a word, so that `-1` becomes all ones
+    pub fn word(&self, value: i64) -> u64 {
+        match self.word_size {
+            4 => value as u32 as u64,
+            _ => value as u64,
+        }
+    }
+
+    pub fn read_word(&self, memory: &mut dyn Memory, address: u64) -> Result<u64, MemoryError> {
+        let mut bytes = [0u8; 8];
+        let bytes = &mut bytes[..self.word_size];
+        memory.read(address, bytes)?;
+        if self.big_endian {
+            bytes.reverse();
+        }
+        Ok(bytes.iter().rev().fold(0, |word, &b| word << 8 | b as u64))
+    }
+
+    pub fn write_word(&self, memory: &mut dyn Memory, address: u64, word: u64) -> Result<(), MemoryError> {
+        let mut bytes = word.to_le_bytes();
+        let bytes = &mut bytes[..self.word_size];
+        if self.big_endian {
+            bytes.reverse();
+        }
+        memory.write(address, bytes)
+    }
+
+    /// Reads an `N` word parameter block
+    pub fn read_block<const N: usize>(&self, memory: &mut dyn Memory, address: u64) -> Result<[u64; N], MemoryError> {
+        let mut block = [0; N];
+        for (i, word) in block.iter_mut().enumerate() {
+            *word = self.read_word(memory, address + (i * self.word_size) as u64)?;
+        }
+        Ok(block)
+    }
+
+    pub fn read_bytes(&self, memory: &mut dyn Memory, address: u64, len: u64) -> Result<Vec<u8>, MemoryError> {
+        let mut bytes = vec![0; len.try_into().map_err(|_| MemoryError { address })?];
+        memory.read(address, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads a nul-terminated string, without the nul
+    pub fn read_cstr(&self, memory: &mut dyn Memory, mut address: u64) -> Result<Vec<u8>, MemoryError> {
+        let mut bytes = Vec::new();
+        loop {
+            let mut byte = [0];
+            memory.read(address, &mut byte)?;
+            match byte[0] {
+                0 => return Ok(bytes),
+                b => bytes.push(b),
+            }
+            address += 1;
+        }
+    }
+}
+
+#[test]
+fn target_words() {
+    let mut memory = VecMemory {
+        base: 0x1000,
+        bytes: vec![0; 16],
+    };
+    Target::ARM32.write_word(&mut memory, 0x1000, 0x1234_5678).unwrap();
+    assert_eq!(&memory.bytes[..4], &[0x78, 0x56, 0x34, 0x12]);
+    let big = Target {
+        big_endian: true,
+        ..Target::ARM32
+    };
+    assert_eq!(big.read_word(&mut memory, 0x1000), Ok(0x7856_3412));
+    Target::AARCH64.write_word(&mut memory, 0x1008, u64::MAX).unwrap();
+    assert_eq!(Target::ARM32.read_block::<3>(&mut memory, 0x1004), Ok([0, u32::MAX as u64, u32::MAX as u64]));
+    assert_eq!(Target::ARM32.signed(u32::MAX as u64), -1);
+    assert_eq!(Target::ARM32.read_word(&mut memory, 0x100e), Err(MemoryError { address: 0x100e }));
+}
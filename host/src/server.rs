@@ -0,0 +1,506 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use semihosting::{Exception, Extensions, Mode, Syscall, MAGIC};
+use crate::memory::{Memory, MemoryError, Target};
+
+const EBADF: i32 = 9;
+const EFAULT: i32 = 14;
+const EINVAL: i32 = 22;
+const ESPIPE: i32 = 29;
+const ENOSYS: i32 = 38;
+
+/// Rate of the `SYS_ELAPSED` tick counter
+pub const TICK_FREQ: u64 = 1_000_000;
+
+/// What the target should see after a semihosting call
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Response {
+    /// Resume with this value in the return register
+    Return(u64),
+    /// The target reported an exception or exit, which is up to the caller
+    ///
+    /// The target may be resumed, for example after a breakpoint.
+    Exception {
+        reason: u64,
+        subcode: Option<u64>,
+    },
+}
+
+impl Response {
+    pub fn exception(&self) -> Option<Exception> {
+        match *self {
+            Response::Exception { reason, .. } => usize::try_from(reason).ok().and_then(|r| Exception::try_from(r).ok()),
+            Response::Return(_) => None,
+        }
+    }
+}
+
+enum Entry {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+    Features(Cursor<Vec<u8>>),
+}
+
+enum Error {
+    Memory(MemoryError),
+    Io(io::Error),
+    Errno(i32),
+}
+
+impl From<MemoryError> for Error {
+    fn from(e: MemoryError) -> Self {
+        Error::Memory(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl Error {
+    fn errno(&self) -> i32 {
+        match self {
+            Error::Memory(_) => EFAULT,
+            Error::Io(e) => e.raw_os_error().unwrap_or(EINVAL),
+            Error::Errno(errno) => *errno,
+        }
+    }
+}
+
+/// Maps a semihosting `fopen` mode onto the host's open options
+fn open_options(mode: Mode) -> OpenOptions {
+    let update = mode.contains(Mode::MODE_UPDATE);
+    let mut options = OpenOptions::new();
+    if mode.contains(Mode::MODE_APPEND) {
+        options.append(true).create(true).read(update);
+    } else if mode.contains(Mode::MODE_READ_WRITE) {
+        options.write(true).create(true).truncate(true).read(update);
+    } else {
+        options.read(true).write(update);
+    }
+    options
+}
+
+/// Services semihosting calls against the host OS
+///
+/// Hand each trap to [`handle`](Self::handle) with the operation and
+/// parameter registers, then resume the target as the [`Response`] says.
+pub struct SemihostingServer {
+    target: Target,
+    files: BTreeMap<u64, Entry>,
+    errno: i32,
+    start: Instant,
+    cmdline: Vec<u8>,
+    heap_info: [u64; 4],
+    stdin: Box<dyn Read + Send>,
+    stdout: Box<dyn Write + Send>,
+    stderr: Box<dyn Write + Send>,
+}
+
+impl SemihostingServer {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            files: BTreeMap::new(),
+            errno: 0,
+            start: Instant::now(),
+            cmdline: Vec::new(),
+            heap_info: [0; 4],
+            stdin: Box::new(io::stdin()),
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+        }
+    }
+
+    /// The command line returned by `SYS_GET_CMDLINE`
+    pub fn set_cmdline(&mut self, cmdline: &str) {
+        self.cmdline = cmdline.as_bytes().to_vec();
+    }
+
+    /// Heap base and limit, then stack base and limit, as returned by `SYS_HEAPINFO`
+    pub fn set_heap_info(&mut self, heap_info: [u64; 4]) {
+        self.heap_info = heap_info;
+    }
+
+    /// Replaces the host's standard streams behind `:tt`
+    pub fn set_console(&mut self, stdin: Box<dyn Read + Send>, stdout: Box<dyn Write + Send>, stderr: Box<dyn Write + Send>) {
+        self.stdin = stdin;
+        self.stdout = stdout;
+        self.stderr = stderr;
+    }
+
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// The error returned by `SYS_ERRNO`
+    pub fn errno(&self) -> i32 {
+        self.errno
+    }
+
+    /// Services one call, given the operation number and parameter register
+    pub fn handle(&mut self, operation: u64, parameter: u64, memory: &mut dyn Memory) -> Response {
+        let syscall = match usize::try_from(operation).ok().and_then(|op| Syscall::try_from(op).ok()) {
+            Some(syscall) => syscall,
+            None => return self.fail(ENOSYS),
+        };
+        match self.dispatch(syscall, parameter, memory) {
+            Ok(response) => response,
+            Err(e) => self.fail(e.errno()),
+        }
+    }
+
+    fn fail(&mut self, errno: i32) -> Response {
+        self.errno = errno;
+        Response::Return(self.target.word(-1))
+    }
+
+    fn ok(&self, value: i64) -> Result<Response, Error> {
+        Ok(Response::Return(self.target.word(value)))
+    }
+
+    fn entry(&mut self, fd: u64) -> Result<&mut Entry, Error> {
+        self.files.get_mut(&fd).ok_or(Error::Errno(EBADF))
+    }
+
+    fn allocate(&mut self, entry: Entry) -> u64 {
+        // zero is never a valid handle
+        let fd = (1..).find(|fd| !self.files.contains_key(fd)).unwrap();
+        self.files.insert(fd, entry);
+        fd
+    }
+
+    fn path(&self, memory: &mut dyn Memory, address: u64, len: u64) -> Result<String, Error> {
+        let bytes = self.target.read_bytes(memory, address, len)?;
+        String::from_utf8(bytes).map_err(|_| Error::Errno(EINVAL))
+    }
+
+    fn dispatch(&mut self, syscall: Syscall, parameter: u64, memory: &mut dyn Memory) -> Result<Response, Error> {
+        let target = self.target;
+        match syscall {
+            Syscall::Open => {
+                let [name, mode, len] = target.read_block(memory, parameter)?;
+                if mode > 11 {
+                    return Err(Error::Errno(EINVAL))
+                }
+                let mode = Mode::from_bits_truncate(mode as u32);
+                let name = self.path(memory, name, len)?;
+                let entry = match &name[..] {
+                    ":tt" if mode.contains(Mode::MODE_APPEND) => Entry::Stderr,
+                    ":tt" if mode.contains(Mode::MODE_READ_WRITE) => Entry::Stdout,
+                    ":tt" => Entry::Stdin,
+                    ":semihosting-features" => {
+                        let mut features = MAGIC.to_vec();
+                        features.push((Extensions::EXIT_EXTENDED | Extensions::STDOUT_STDERR).bits() as u8);
+                        Entry::Features(Cursor::new(features))
+                    },
+                    path => Entry::File(open_options(mode).open(path)?),
+                };
+                let fd = self.allocate(entry);
+                self.ok(fd as i64)
+            },
+            Syscall::Close => {
+                let [fd] = target.read_block(memory, parameter)?;
+                self.files.remove(&fd).ok_or(Error::Errno(EBADF))?;
+                self.ok(0)
+            },
+            Syscall::WriteC => {
+                let mut c = [0];
+                memory.read(parameter, &mut c)?;
+                self.stdout.write_all(&c)?;
+                self.stdout.flush()?;
+                self.ok(0)
+            },
+            Syscall::Write0 => {
+                let s = target.read_cstr(memory, parameter)?;
+                self.stdout.write_all(&s)?;
+                self.stdout.flush()?;
+                self.ok(0)
+            },
+            Syscall::Write => {
+                let [fd, address, len] = target.read_block(memory, parameter)?;
+                let data = target.read_bytes(memory, address, len)?;
+                let res = match self.files.get_mut(&fd).ok_or(Error::Errno(EBADF))? {
+                    Entry::Stdout => self.stdout.write_all(&data).and_then(|()| self.stdout.flush()),
+                    Entry::Stderr => self.stderr.write_all(&data).and_then(|()| self.stderr.flush()),
+                    Entry::File(file) => file.write_all(&data),
+                    Entry::Stdin | Entry::Features(_) => return Err(Error::Errno(EBADF)),
+                };
+                // the result is the number of bytes not written
+                match res {
+                    Ok(()) => self.ok(0),
+                    Err(e) => {
+                        self.errno = Error::Io(e).errno();
+                        self.ok(len as i64)
+                    },
+                }
+            },
+            Syscall::Read => {
+                let [fd, address, len] = target.read_block(memory, parameter)?;
+                let mut buffer = vec![0; usize::try_from(len).map_err(|_| Error::Errno(EINVAL))?];
+                let res = match self.files.get_mut(&fd).ok_or(Error::Errno(EBADF))? {
+                    Entry::Stdin => self.stdin.read(&mut buffer),
+                    Entry::File(file) => read_full(file, &mut buffer),
+                    Entry::Features(features) => read_full(features, &mut buffer),
+                    Entry::Stdout | Entry::Stderr => return Err(Error::Errno(EBADF)),
+                };
+                match res {
+                    Ok(read) => {
+                        memory.write(address, &buffer[..read])?;
+                        self.ok((len - read as u64) as i64)
+                    },
+                    Err(e) => {
+                        self.errno = Error::Io(e).errno();
+                        self.ok(len as i64)
+                    },
+                }
+            },
+            Syscall::ReadC => {
+                let mut c = [0];
+                match self.stdin.read(&mut c)? {
+                    0 => Err(Error::Errno(EINVAL)),
+                    _ => self.ok(c[0] as i64),
+                }
+            },
+            Syscall::IsError => {
+                let [status] = target.read_block(memory, parameter)?;
+                self.ok((target.signed(status) < 0) as i64)
+            },
+            Syscall::IsTTY => {
+                let [fd] = target.read_block(memory, parameter)?;
+                let tty = match self.entry(fd)? {
+                    Entry::Stdin | Entry::Stdout | Entry::Stderr => 1,
+                    Entry::File(_) | Entry::Features(_) => 0,
+                };
+                self.ok(tty)
+            },
+            Syscall::Seek => {
+                let [fd, position] = target.read_block(memory, parameter)?;
+                match self.entry(fd)? {
+                    Entry::File(file) => file.seek(SeekFrom::Start(position)).map(drop)?,
+                    Entry::Features(features) => features.set_position(position),
+                    Entry::Stdin | Entry::Stdout | Entry::Stderr => return Err(Error::Errno(ESPIPE)),
+                }
+                self.ok(0)
+            },
+            Syscall::FLen => {
+                let [fd] = target.read_block(memory, parameter)?;
+                let len = match self.entry(fd)? {
+                    Entry::File(file) => file.metadata()?.len(),
+                    Entry::Features(features) => features.get_ref().len() as u64,
+                    Entry::Stdin | Entry::Stdout | Entry::Stderr => return Err(Error::Errno(EINVAL)),
+                };
+                self.ok(len as i64)
+            },
+            Syscall::TmpNam => {
+                let [address, id, len] = target.read_block(memory, parameter)?;
+                let name = std::env::temp_dir().join(format!("semihosting-{}-{:03}", std::process::id(), id & 0xff));
+                let mut name = name.to_str().ok_or(Error::Errno(EINVAL))?.as_bytes().to_vec();
+                name.push(0);
+                if name.len() as u64 > len {
+                    return Err(Error::Errno(EINVAL))
+                }
+                memory.write(address, &name)?;
+                self.ok(0)
+            },
+            Syscall::Remove => {
+                let [address, len] = target.read_block(memory, parameter)?;
+                fs::remove_file(self.path(memory, address, len)?)?;
+                self.ok(0)
+            },
+            Syscall::Rename => {
+                let [from, from_len, to, to_len] = target.read_block(memory, parameter)?;
+                fs::rename(self.path(memory, from, from_len)?, self.path(memory, to, to_len)?)?;
+                self.ok(0)
+            },
+            Syscall::Clock => self.ok((self.start.elapsed().as_millis() / 10) as i64),
+            Syscall::Time => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| Error::Errno(EINVAL))?;
+                self.ok(now.as_secs() as i64)
+            },
+            Syscall::System => {
+                let [address, len] = target.read_block(memory, parameter)?;
+                let command = self.path(memory, address, len)?;
+                #[cfg(windows)]
+                let status = std::process::Command::new("cmd").arg("/C").arg(command).status()?;
+                #[cfg(not(windows))]
+                let status = std::process::Command::new("sh").arg("-c").arg(command).status()?;
+                self.ok(status.code().unwrap_or(-1) as i64)
+            },
+            Syscall::Errno => self.ok(self.errno as i64),
+            Syscall::GetCmdline => {
+                let [address, len] = target.read_block(memory, parameter)?;
+                if self.cmdline.len() as u64 >= len {
+                    return Err(Error::Errno(EINVAL))
+                }
+                let mut cmdline = self.cmdline.clone();
+                cmdline.push(0);
+                memory.write(address, &cmdline)?;
+                target.write_word(memory, parameter + target.word_size as u64, self.cmdline.len() as u64)?;
+                self.ok(0)
+            },
+            Syscall::HeapInfo => {
+                let block = target.read_word(memory, parameter)?;
+                for (i, &word) in self.heap_info.iter().enumerate() {
+                    target.write_word(memory, block + (i * target.word_size) as u64, word)?;
+                }
+                self.ok(0)
+            },
+            // there is no mode to switch on the host side
+            Syscall::EnterSVC => self.ok(0),
+            Syscall::ReportException if target.word_size == 4 => Ok(Response::Exception {
+                reason: parameter,
+                subcode: None,
+            }),
+            Syscall::ReportException | Syscall::ReportExceptionExtended => {
+                let [reason, subcode] = target.read_block(memory, parameter)?;
+                Ok(Response::Exception {
+                    reason,
+                    subcode: Some(subcode),
+                })
+            },
+            Syscall::Elapsed => {
+                let ticks = self.start.elapsed().as_micros() as u64;
+                match target.word_size {
+                    4 => {
+                        target.write_word(memory, parameter, ticks & 0xffff_ffff)?;
+                        target.write_word(memory, parameter + 4, ticks >> 32)?;
+                    },
+                    _ => target.write_word(memory, parameter, ticks)?,
+                }
+                self.ok(0)
+            },
+            Syscall::TickFreq => self.ok(TICK_FREQ as i64),
+        }
+    }
+}
+
+/// Reads until `buffer` is full or the end of the file
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+struct Harness {
+    server: SemihostingServer,
+    memory: crate::memory::VecMemory,
+    next: u64,
+}
+
+#[cfg(test)]
+impl Harness {
+    const BASE: u64 = 0x2000_0000;
+
+    fn new() -> Self {
+        Self {
+            server: SemihostingServer::new(Target::ARM32),
+            memory: crate::memory::VecMemory {
+                base: Self::BASE,
+                bytes: vec![0; 0x1000],
+            },
+            next: Self::BASE,
+        }
+    }
+
+    fn alloc(&mut self, bytes: &[u8]) -> u64 {
+        let address = self.next;
+        self.memory.write(address, bytes).unwrap();
+        self.next += (bytes.len() as u64 + 3) & !3;
+        address
+    }
+
+    fn block(&mut self, words: &[u64]) -> u64 {
+        let bytes: Vec<u8> = words.iter().flat_map(|&w| (w as u32).to_le_bytes().to_vec()).collect();
+        self.alloc(&bytes)
+    }
+
+    fn call(&mut self, syscall: Syscall, words: &[u64]) -> Response {
+        let block = self.block(words);
+        self.server.handle(usize::from(syscall) as u64, block, &mut self.memory)
+    }
+
+    fn open(&mut self, path: &str, mode: Mode) -> u64 {
+        let name = self.alloc(path.as_bytes());
+        match self.call(Syscall::Open, &[name, mode.bits() as u64, path.len() as u64]) {
+            Response::Return(fd) if fd != u32::MAX as u64 => fd,
+            response => panic!("open {} failed: {:?}", path, response),
+        }
+    }
+}
+
+#[test]
+fn server_files() {
+    let mut h = Harness::new();
+    let path = std::env::temp_dir().join(format!("semihosting-host-test-{}", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    let fd = h.open(path, Mode::MODE_READ_WRITE | Mode::BINARY);
+    let data = h.alloc(b"hello");
+    assert_eq!(h.call(Syscall::Write, &[fd, data, 5]), Response::Return(0));
+    assert_eq!(h.call(Syscall::IsTTY, &[fd]), Response::Return(0));
+    assert_eq!(h.call(Syscall::Close, &[fd]), Response::Return(0));
+
+    let fd = h.open(path, Mode::MODE_READ_ONLY);
+    assert_eq!(h.call(Syscall::FLen, &[fd]), Response::Return(5));
+    assert_eq!(h.call(Syscall::Seek, &[fd, 1]), Response::Return(0));
+    let buffer = h.alloc(&[0; 8]);
+    // four bytes read, four not
+    assert_eq!(h.call(Syscall::Read, &[fd, buffer, 8]), Response::Return(4));
+    assert_eq!(&h.memory.bytes[(buffer - Harness::BASE) as usize..][..4], b"ello");
+    assert_eq!(h.call(Syscall::Close, &[fd]), Response::Return(0));
+
+    let name = h.alloc(path.as_bytes());
+    assert_eq!(h.call(Syscall::Remove, &[name, path.len() as u64]), Response::Return(0));
+    assert_eq!(h.call(Syscall::Close, &[fd]), Response::Return(u32::MAX as u64));
+    assert_eq!(h.call(Syscall::Errno, &[]), Response::Return(EBADF as u64));
+}
+
+#[test]
+fn server_features() {
+    let mut h = Harness::new();
+    let fd = h.open(":semihosting-features", Mode::MODE_READ_ONLY | Mode::BINARY);
+    assert_eq!(h.call(Syscall::FLen, &[fd]), Response::Return(5));
+    let buffer = h.alloc(&[0; 5]);
+    assert_eq!(h.call(Syscall::Read, &[fd, buffer, 5]), Response::Return(0));
+    assert_eq!(&h.memory.bytes[(buffer - Harness::BASE) as usize..][..5], b"SHFB\x03");
+
+    let tty = h.open(":tt", Mode::MODE_APPEND);
+    assert_eq!(h.call(Syscall::IsTTY, &[tty]), Response::Return(1));
+    assert_eq!(h.call(Syscall::Seek, &[tty, 0]), Response::Return(u32::MAX as u64));
+}
+
+#[test]
+fn server_misc() {
+    let mut h = Harness::new();
+    h.server.set_cmdline("firmware --exact");
+    let buffer = h.alloc(&[0xff; 32]);
+    let block = h.block(&[buffer, 32]);
+    assert_eq!(h.server.handle(usize::from(Syscall::GetCmdline) as u64, block, &mut h.memory), Response::Return(0));
+    assert_eq!(&h.memory.bytes[(buffer - Harness::BASE) as usize..][..17], b"firmware --exact\0");
+    assert_eq!(Target::ARM32.read_word(&mut h.memory, block + 4), Ok(16));
+
+    assert_eq!(h.call(Syscall::IsError, &[u32::MAX as u64]), Response::Return(1));
+    assert_eq!(h.call(Syscall::IsError, &[3]), Response::Return(0));
+    assert_eq!(h.server.handle(11, 0, &mut h.memory), Response::Return(u32::MAX as u64));
+
+    let exit = h.server.handle(usize::from(Syscall::ReportException) as u64, 0x20026, &mut h.memory);
+    assert_eq!(exit.exception(), Some(Exception::ApplicationExit));
+    let exit = h.call(Syscall::ReportExceptionExtended, &[0x20026, 3]);
+    assert_eq!(exit, Response::Exception { reason: 0x20026, subcode: Some(3) });
+}
@@ -0,0 +1,44 @@
+//! Reentrancy flags for the tracing and logging buffers
+//!
+//! These keep an interrupt from writing to a buffer that the code it
+//! interrupted is in the middle of, and are only sound on a single core.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Claims `flag`, or returns false if something else holds it
+#[cfg(feature = "critical-section")]
+pub(crate) fn try_acquire(flag: &AtomicBool) -> bool {
+    critical_section::with(|_| match flag.load(Ordering::Relaxed) {
+        true => false,
+        false => {
+            flag.store(true, Ordering::Relaxed);
+            true
+        },
+    })
+}
+
+#[cfg(all(not(feature = "critical-section"), target_has_atomic = "8"))]
+#[inline]
+pub(crate) fn try_acquire(flag: &AtomicBool) -> bool {
+    !flag.swap(true, Ordering::Acquire)
+}
+
+// without compare-and-swap, an interrupt between the load and store can
+// still get in, so enable `critical-section` when tracing or logging from
+// interrupts
+#[cfg(all(not(feature = "critical-section"), not(target_has_atomic = "8")))]
+#[inline]
+pub(crate) fn try_acquire(flag: &AtomicBool) -> bool {
+    match flag.load(Ordering::Acquire) {
+        true => false,
+        false => {
+            flag.store(true, Ordering::Relaxed);
+            true
+        },
+    }
+}
+
+#[inline]
+pub(crate) fn release(flag: &AtomicBool) {
+    flag.store(false, Ordering::Release);
+}
@@ -0,0 +1,129 @@
+use core::mem::size_of;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cstrptr::CStr;
+use crate::io::{Errno, OwnedHandle, WriteAllError};
+use crate::Mode;
+
+// Raw profile format written by LLVM 19 and newer, see `InstrProfData.inc`.
+// The compiler adds variant flags to the version it emits, so that is what
+// goes in the header.
+const RAW_VERSION: u64 = 10;
+const VARIANT_MASKS_ALL: u64 = 0xffff_ffff_0000_0000;
+const VALUE_KIND_LAST: u64 = 2;
+
+/// Size of an `__llvm_profile_data` record, which is padded to 8 bytes
+const DATA_RECORD_SIZE: usize = (30 + 4 * size_of::<usize>() + 7) & !7;
+
+#[cfg(target_pointer_width = "64")]
+const MAGIC: u64 = raw_magic(b'r');
+#[cfg(not(target_pointer_width = "64"))]
+const MAGIC: u64 = raw_magic(b'R');
+
+const fn raw_magic(r: u8) -> u64 {
+    255 << 56 | (b'l' as u64) << 48 | (b'p' as u64) << 40 | (b'r' as u64) << 32
+        | (b'o' as u64) << 24 | (b'f' as u64) << 16 | (r as u64) << 8 | 129
+}
+
+extern "C" {
+    static __llvm_profile_raw_version: u64;
+    static __start___llvm_prf_data: u8;
+    static __stop___llvm_prf_data: u8;
+    static __start___llvm_prf_cnts: u8;
+    static __stop___llvm_prf_cnts: u8;
+    static __start___llvm_prf_names: u8;
+    static __stop___llvm_prf_names: u8;
+}
+
+/// Stands in for the profiler runtime, which instrumented code links against
+#[no_mangle]
+#[used]
+#[allow(non_upper_case_globals)]
+static __llvm_profile_runtime: i32 = 0;
+
+static PATH_PTR: AtomicUsize = AtomicUsize::new(0);
+static PATH_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the host path that [`exit`](crate::exit) writes the profile to
+///
+/// Defaults to `default.profraw`, like the LLVM profiler runtime.
+pub fn set_path(path: &'static CStr) {
+    let path = path.to_bytes();
+    PATH_LEN.store(0, Ordering::Relaxed);
+    PATH_PTR.store(path.as_ptr() as usize, Ordering::Relaxed);
+    PATH_LEN.store(path.len() + 1, Ordering::Relaxed);
+}
+
+fn path() -> &'static CStr {
+    match PATH_LEN.load(Ordering::Relaxed) {
+        0 => cstrptr::cstr!("default.profraw"),
+        len => unsafe {
+            CStr::from_bytes_with_nul_unchecked(slice::from_raw_parts(PATH_PTR.load(Ordering::Relaxed) as *const u8, len))
+        },
+    }
+}
+
+unsafe fn section(start: &'static u8, stop: &'static u8) -> &'static [u8] {
+    let start = start as *const u8;
+    slice::from_raw_parts(start, (stop as *const u8 as usize).saturating_sub(start as usize))
+}
+
+#[inline]
+fn padding(len: usize) -> &'static [u8] {
+    &[0u8; 8][..(8 - len % 8) % 8]
+}
+
+/// Writes the current coverage counters as a `.profraw` file
+///
+/// Firmware must be built with `-C instrument-coverage -Z no-profiler-runtime`,
+/// and the linker must keep the `__llvm_prf_*` sections so that their
+/// `__start_` and `__stop_` symbols are defined. `__llvm_prf_cnts` has to be
+/// zero-initialized in RAM. The result can be merged with `llvm-profdata merge`.
+///
+/// Fails with `Invalid` if the compiler emits a format other than version 10.
+pub fn dump<E: Errno>(path: &CStr) -> Result<(), WriteAllError<E>> {
+    let version = unsafe { core::ptr::read_volatile(&__llvm_profile_raw_version) };
+    if version & !VARIANT_MASKS_ALL != RAW_VERSION {
+        return Err(WriteAllError::Invalid)
+    }
+    let (data, counters, names) = unsafe {
+        (
+            section(&__start___llvm_prf_data, &__stop___llvm_prf_data),
+            section(&__start___llvm_prf_cnts, &__stop___llvm_prf_cnts),
+            section(&__start___llvm_prf_names, &__stop___llvm_prf_names),
+        )
+    };
+    let data_begin = data.as_ptr() as u64;
+
+    let header: [u64; 16] = [
+        MAGIC,
+        version,
+        0, // binary ids size
+        (data.len() / DATA_RECORD_SIZE) as u64,
+        padding(data.len()).len() as u64,
+        (counters.len() / size_of::<u64>()) as u64,
+        padding(counters.len()).len() as u64,
+        0, // bitmap bytes
+        0, // padding after bitmap
+        names.len() as u64,
+        (counters.as_ptr() as u64).wrapping_sub(data_begin),
+        0u64.wrapping_sub(data_begin), // no bitmap
+        names.as_ptr() as u64,
+        0, // vtables
+        0, // vtable names size
+        VALUE_KIND_LAST,
+    ];
+    let header = unsafe {
+        slice::from_raw_parts(header.as_ptr() as *const u8, header.len() * size_of::<u64>())
+    };
+
+    let file = OwnedHandle::open(path, Mode::MODE_READ_WRITE | Mode::BINARY).map_err(WriteAllError::Io)?;
+    for chunk in &[header, data, padding(data.len()), counters, padding(counters.len()), names, padding(names.len())] {
+        file.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn dump_on_exit() {
+    let _ = dump::<()>(path());
+}
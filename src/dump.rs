@@ -0,0 +1,204 @@
+use core::slice;
+use cstrptr::CStr;
+use crate::io::{Errno, OwnedHandle, WriteAllError};
+use crate::Mode;
+
+/// Writes `len` bytes of memory starting at `start` to a host file
+///
+/// # Safety
+///
+/// The whole region must be readable.
+pub unsafe fn dump_region<E: Errno>(path: &CStr, start: *const u8, len: usize) -> Result<(), WriteAllError<E>> {
+    let file = OwnedHandle::open(path, Mode::MODE_READ_WRITE | Mode::BINARY).map_err(WriteAllError::Io)?;
+    file.write_all(slice::from_raw_parts(start, len))
+}
+
+/// Register state saved in the core dump's `NT_PRSTATUS` note
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct Registers {
+    /// r0-r12, sp, lr and pc
+    pub r: [u32; 16],
+    /// cpsr, or xpsr on M-profile
+    pub psr: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Region {
+    start: usize,
+    len: usize,
+}
+
+/// An ELF core dump of up to `N` memory regions, written over semihosting
+///
+/// Nothing is allocated, so this can be used from a fault handler. The
+/// result loads with `gdb firmware.elf core`.
+///
+/// ```ignore
+/// let mut core = CoreDump::<2>::new(registers);
+/// unsafe {
+///     core.region(0x2000_0000 as *const u8, 0x1_0000);
+/// }
+/// core.write::<()>(cstr!("core"))?;
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct CoreDump<const N: usize> {
+    registers: Registers,
+    regions: [Region; N],
+    count: usize,
+}
+
+const EHDR_LEN: usize = 52;
+const PHDR_LEN: usize = 32;
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+// 32-bit ARM elf_prstatus: pr_reg holds r0-r15, cpsr and orig_r0
+const PRSTATUS_LEN: usize = 148;
+const PRSTATUS_REG_OFFSET: usize = 72;
+const NOTE_LEN: usize = 12 + NOTE_NAME.len() + PRSTATUS_LEN;
+
+const ET_CORE: u16 = 4;
+const EM_ARM: u16 = 40;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const PF_RWX: u32 = 7;
+
+/// Fixed-size buffer for building headers in native byte order
+struct Builder<const L: usize> {
+    bytes: [u8; L],
+    len: usize,
+}
+
+impl<const L: usize> Builder<L> {
+    fn new() -> Self {
+        Self {
+            bytes: [0; L],
+            len: 0,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        self
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.bytes(&value.to_ne_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.bytes(&value.to_ne_bytes())
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl<const N: usize> CoreDump<N> {
+    pub const fn new(registers: Registers) -> Self {
+        Self {
+            registers,
+            regions: [Region { start: 0, len: 0 }; N],
+            count: 0,
+        }
+    }
+
+    /// Adds a memory region, returning `false` if all `N` slots are used
+    ///
+    /// # Safety
+    ///
+    /// The whole region must stay readable until the dump is written.
+    pub unsafe fn region(&mut self, start: *const u8, len: usize) -> bool {
+        match self.regions.get_mut(self.count) {
+            Some(region) => {
+                *region = Region {
+                    start: start as usize,
+                    len,
+                };
+                self.count += 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn regions(&self) -> &[Region] {
+        &self.regions[..self.count]
+    }
+
+    pub fn write<E: Errno>(&self, path: &CStr) -> Result<(), WriteAllError<E>> {
+        let file = OwnedHandle::open(path, Mode::MODE_READ_WRITE | Mode::BINARY).map_err(WriteAllError::Io)?;
+        let phnum = 1 + self.count;
+        let note_offset = EHDR_LEN + PHDR_LEN * phnum;
+
+        let mut ehdr = Builder::<EHDR_LEN>::new();
+        ehdr.bytes(b"\x7fELF")
+            .bytes(&[
+                1, // ELFCLASS32
+                if cfg!(target_endian = "big") { 2 } else { 1 },
+                1, // EV_CURRENT
+            ])
+            .bytes(&[0; 9])
+            .u16(ET_CORE)
+            .u16(EM_ARM)
+            .u32(1) // e_version
+            .u32(0) // e_entry
+            .u32(EHDR_LEN as u32) // e_phoff
+            .u32(0) // e_shoff
+            .u32(0) // e_flags
+            .u16(EHDR_LEN as u16)
+            .u16(PHDR_LEN as u16)
+            .u16(phnum as u16)
+            .u16(0) // e_shentsize
+            .u16(0) // e_shnum
+            .u16(0); // e_shstrndx
+        file.write_all(ehdr.as_bytes())?;
+
+        let mut phdr = Builder::<PHDR_LEN>::new();
+        phdr.u32(PT_NOTE)
+            .u32(note_offset as u32)
+            .u32(0)
+            .u32(0)
+            .u32(NOTE_LEN as u32)
+            .u32(0)
+            .u32(0)
+            .u32(4);
+        file.write_all(phdr.as_bytes())?;
+
+        let mut offset = note_offset + NOTE_LEN;
+        for region in self.regions() {
+            let mut phdr = Builder::<PHDR_LEN>::new();
+            phdr.u32(PT_LOAD)
+                .u32(offset as u32)
+                .u32(region.start as u32) // p_vaddr
+                .u32(region.start as u32) // p_paddr
+                .u32(region.len as u32) // p_filesz
+                .u32(region.len as u32) // p_memsz
+                .u32(PF_RWX)
+                .u32(1);
+            file.write_all(phdr.as_bytes())?;
+            offset += region.len;
+        }
+
+        let mut note = Builder::<NOTE_LEN>::new();
+        note.u32(5) // "CORE\0"
+            .u32(PRSTATUS_LEN as u32)
+            .u32(NT_PRSTATUS)
+            .bytes(NOTE_NAME)
+            .bytes(&[0; PRSTATUS_REG_OFFSET]);
+        for &reg in &self.registers.r {
+            note.u32(reg);
+        }
+        note.u32(self.registers.psr)
+            .u32(self.registers.r[0]) // orig_r0
+            .u32(0); // pr_fpvalid
+        file.write_all(note.as_bytes())?;
+
+        for region in self.regions() {
+            file.write_all(unsafe { slice::from_raw_parts(region.start as *const u8, region.len) })?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use core::num::NonZeroUsize;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cstrptr::CStr;
+use crate::io::{Errno, Handle, WriteAllError};
+use crate::Mode;
+
+/// Event logs start with this, followed by [`VERSION`] and three reserved bytes
+///
+/// Each record after the header is a little-endian `u16` length followed by
+/// that many bytes of CBOR: a two element array of the event id and a map of
+/// its fields.
+pub const MAGIC: [u8; 4] = *b"SHEV";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 8;
+
+/// Largest encoded record, including the length prefix
+pub const MAX_LEN: usize = 256;
+
+static OUTPUT: AtomicUsize = AtomicUsize::new(0);
+
+/// Starts writing events logged with [`event!`](crate::event!) to a host file
+pub fn start<E: Errno>(path: &CStr) -> Result<(), WriteAllError<E>> {
+    stop::<()>().ok();
+    let handle = Handle::open(path, Mode::MODE_READ_WRITE | Mode::BINARY).map_err(WriteAllError::Io)?;
+    let mut header = [0u8; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = VERSION;
+    handle.write_all(&header)?;
+    OUTPUT.store(handle.fd().get(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Closes the file opened by [`start`]
+pub fn stop<E: Errno>() -> Result<(), E> {
+    match NonZeroUsize::new(OUTPUT.load(Ordering::Relaxed)) {
+        Some(fd) => {
+            OUTPUT.store(0, Ordering::Relaxed);
+            Handle::from_fd(fd).close()
+        },
+        None => Ok(()),
+    }
+}
+
+/// Appends CBOR data items to a fixed buffer
+///
+/// Writes that don't fit return `None` and may leave a partial item behind.
+pub struct Encoder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    pub fn raw(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len.checked_add(bytes.len())?;
+        self.buffer.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+
+    fn head(&mut self, major: u8, value: u64) -> Option<()> {
+        let major = major << 5;
+        match value {
+            0..=23 => self.raw(&[major | value as u8]),
+            24..=0xff => self.raw(&[major | 24, value as u8]),
+            0x100..=0xffff => {
+                self.raw(&[major | 25])?;
+                self.raw(&(value as u16).to_be_bytes())
+            },
+            0x1_0000..=0xffff_ffff => {
+                self.raw(&[major | 26])?;
+                self.raw(&(value as u32).to_be_bytes())
+            },
+            _ => {
+                self.raw(&[major | 27])?;
+                self.raw(&value.to_be_bytes())
+            },
+        }
+    }
+
+    #[inline]
+    pub fn uint(&mut self, value: u64) -> Option<()> {
+        self.head(0, value)
+    }
+
+    pub fn int(&mut self, value: i64) -> Option<()> {
+        match value {
+            0..=i64::MAX => self.head(0, value as u64),
+            // negative integers are encoded as -1 - n
+            _ => self.head(1, !(value as u64)),
+        }
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> Option<()> {
+        self.head(2, value.len() as u64)?;
+        self.raw(value)
+    }
+
+    pub fn text(&mut self, value: &str) -> Option<()> {
+        self.head(3, value.len() as u64)?;
+        self.raw(value.as_bytes())
+    }
+
+    #[inline]
+    pub fn array(&mut self, len: usize) -> Option<()> {
+        self.head(4, len as u64)
+    }
+
+    /// Starts a map of unknown length, ended by [`end`](Self::end)
+    #[inline]
+    pub fn map_start(&mut self) -> Option<()> {
+        self.raw(&[0xbf])
+    }
+
+    #[inline]
+    pub fn end(&mut self) -> Option<()> {
+        self.raw(&[0xff])
+    }
+
+    #[inline]
+    pub fn bool(&mut self, value: bool) -> Option<()> {
+        self.raw(&[if value { 0xf5 } else { 0xf4 }])
+    }
+
+    #[inline]
+    pub fn null(&mut self) -> Option<()> {
+        self.raw(&[0xf6])
+    }
+
+    pub fn f32(&mut self, value: f32) -> Option<()> {
+        self.raw(&[0xfa])?;
+        self.raw(&value.to_bits().to_be_bytes())
+    }
+
+    pub fn f64(&mut self, value: f64) -> Option<()> {
+        self.raw(&[0xfb])?;
+        self.raw(&value.to_bits().to_be_bytes())
+    }
+}
+
+/// A value that can be recorded as an event field
+pub trait Value {
+    fn encode(&self, encoder: &mut Encoder) -> Option<()>;
+}
+
+macro_rules! impl_value {
+    ($method:ident as $as:ty: $($ty:ty),*) => {
+        $(
+            impl Value for $ty {
+                #[inline]
+                fn encode(&self, encoder: &mut Encoder) -> Option<()> {
+                    encoder.$method(*self as $as)
+                }
+            }
+        )*
+    };
+}
+
+impl_value!(uint as u64: u8, u16, u32, u64, usize);
+impl_value!(int as i64: i8, i16, i32, i64, isize);
+impl_value!(f32 as f32: f32);
+impl_value!(f64 as f64: f64);
+impl_value!(bool as bool: bool);
+
+impl Value for str {
+    #[inline]
+    fn encode(&self, encoder: &mut Encoder) -> Option<()> {
+        encoder.text(self)
+    }
+}
+
+impl Value for [u8] {
+    #[inline]
+    fn encode(&self, encoder: &mut Encoder) -> Option<()> {
+        encoder.bytes(self)
+    }
+}
+
+impl<T: Value> Value for Option<T> {
+    fn encode(&self, encoder: &mut Encoder) -> Option<()> {
+        match self {
+            Some(value) => value.encode(encoder),
+            None => encoder.null(),
+        }
+    }
+}
+
+impl<T: Value + ?Sized> Value for &T {
+    #[inline]
+    fn encode(&self, encoder: &mut Encoder) -> Option<()> {
+        (**self).encode(encoder)
+    }
+}
+
+/// An event record being built on the stack
+///
+/// Fields that don't fit in [`MAX_LEN`] are left out rather than failing
+/// the whole record.
+pub struct Event {
+    buffer: [u8; MAX_LEN],
+    len: usize,
+}
+
+impl Event {
+    pub fn new(id: u32) -> Self {
+        let mut event = Self {
+            buffer: [0; MAX_LEN],
+            len: 2,
+        };
+        // always fits in an empty buffer
+        let _ = event.encode(|encoder| {
+            encoder.array(2)?;
+            encoder.uint(id.into())?;
+            encoder.map_start()
+        });
+        event
+    }
+
+    /// Runs `f` on the rest of the buffer, rolling back if it doesn't fit
+    ///
+    /// One byte is kept back for the end of the field map.
+    fn encode<F: FnOnce(&mut Encoder) -> Option<()>>(&mut self, f: F) -> Option<()> {
+        let mut encoder = Encoder::new(&mut self.buffer[self.len..MAX_LEN - 1]);
+        f(&mut encoder)?;
+        self.len += encoder.len();
+        Some(())
+    }
+
+    pub fn field<V: Value + ?Sized>(mut self, key: &str, value: &V) -> Self {
+        let _ = self.encode(|encoder| {
+            encoder.text(key)?;
+            value.encode(encoder)
+        });
+        self
+    }
+
+    /// The finished record, including its length prefix
+    fn finish(&mut self) -> &[u8] {
+        self.buffer[self.len] = 0xff;
+        let len = self.len + 1;
+        self.buffer[..2].copy_from_slice(&((len - 2) as u16).to_le_bytes());
+        &self.buffer[..len]
+    }
+
+    /// Appends the record to `handle`, which must already have the file header
+    pub fn write_to<E: Errno>(mut self, handle: &Handle) -> Result<(), WriteAllError<E>> {
+        handle.write_all(self.finish())
+    }
+
+    /// Writes the record to the file opened with [`start`], if any
+    pub fn emit<E: Errno>(self) -> Result<(), WriteAllError<E>> {
+        match NonZeroUsize::new(OUTPUT.load(Ordering::Relaxed)) {
+            Some(fd) => self.write_to(&Handle::from_fd(fd)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn event_encoding() {
+    let mut event = Event::new(7)
+        .field("temp", &-12i32)
+        .field("name", "ok")
+        .field("on", &true)
+        .field("big", &1000u32);
+    assert_eq!(event.finish(), &[
+        29, 0,
+        0x82, 0x07, 0xbf,
+        0x64, b't', b'e', b'm', b'p', 0x2b,
+        0x64, b'n', b'a', b'm', b'e', 0x62, b'o', b'k',
+        0x62, b'o', b'n', 0xf5,
+        0x63, b'b', b'i', b'g', 0x19, 0x03, 0xe8,
+        0xff,
+    ][..]);
+
+    // fields that don't fit are skipped, leaving a valid record
+    let long = [0u8; MAX_LEN];
+    let mut event = Event::new(1).field("long", &long[..]).field("n", &1u8);
+    assert_eq!(event.finish(), &[7, 0, 0x82, 0x01, 0xbf, 0x61, b'n', 0x01, 0xff][..]);
+}
@@ -0,0 +1,299 @@
+use crate::dump::Registers;
+use crate::Exception;
+#[cfg(any(arm, thumb))]
+use crate::println;
+
+/// Registers stacked by a Cortex-M exception entry
+///
+/// Laid out like `cortex_m_rt::ExceptionFrame`, so a reference to one can be
+/// cast to this.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+/// `EXC_RETURN` bit that is clear when the frame holds floating-point state
+const EXC_RETURN_BASIC_FRAME: u32 = 1 << 4;
+/// Stacked xPSR bit that is set when a word was skipped to align the frame
+const XPSR_STACK_ALIGN: u32 = 1 << 9;
+/// Size of a frame that also holds s0-s15, FPSCR and a reserved word
+const EXTENDED_FRAME_LEN: u32 = 0x68;
+
+impl ExceptionFrame {
+    /// The stack pointer from before the exception pushed this frame
+    ///
+    /// `exc_return` is the value `lr` held on exception entry, which says
+    /// whether floating-point state was stacked after these registers.
+    pub fn stacked_sp(&self, exc_return: u32) -> u32 {
+        let len = match exc_return & EXC_RETURN_BASIC_FRAME {
+            0 => EXTENDED_FRAME_LEN,
+            _ => core::mem::size_of::<Self>() as u32,
+        };
+        let padding = match self.xpsr & XPSR_STACK_ALIGN {
+            0 => 0,
+            _ => 4,
+        };
+        (self as *const Self as u32).wrapping_add(len + padding)
+    }
+
+    /// The registers available from the frame, with r4-r11 left as zero
+    pub fn registers(&self, exc_return: u32) -> Registers {
+        let mut r = [0; 16];
+        r[..4].copy_from_slice(&[self.r0, self.r1, self.r2, self.r3]);
+        r[12] = self.r12;
+        r[13] = self.stacked_sp(exc_return);
+        r[14] = self.lr;
+        r[15] = self.pc;
+        Registers {
+            r,
+            psr: self.xpsr,
+        }
+    }
+}
+
+#[test]
+fn stacked_sp() {
+    let mut frame = ExceptionFrame {
+        r0: 0,
+        r1: 0,
+        r2: 0,
+        r3: 0,
+        r12: 0,
+        lr: 0,
+        pc: 0,
+        xpsr: 0x0100_0000,
+    };
+    let base = &frame as *const ExceptionFrame as u32;
+    assert_eq!(frame.stacked_sp(0xffff_fffd).wrapping_sub(base), 0x20);
+    assert_eq!(frame.stacked_sp(0xffff_ffed).wrapping_sub(base), 0x68);
+    frame.xpsr |= XPSR_STACK_ALIGN;
+    let base = &frame as *const ExceptionFrame as u32;
+    assert_eq!(frame.stacked_sp(0xffff_fffd).wrapping_sub(base), 0x24);
+    assert_eq!(frame.stacked_sp(0xffff_ffe9).wrapping_sub(base), 0x6c);
+}
+
+mod cfsr {
+    pub const IACCVIOL: u32 = 1 << 0;
+    pub const DACCVIOL: u32 = 1 << 1;
+    pub const MUNSTKERR: u32 = 1 << 3;
+    pub const MSTKERR: u32 = 1 << 4;
+    pub const MLSPERR: u32 = 1 << 5;
+    pub const MMARVALID: u32 = 1 << 7;
+    pub const IBUSERR: u32 = 1 << 8;
+    pub const PRECISERR: u32 = 1 << 9;
+    pub const IMPRECISERR: u32 = 1 << 10;
+    pub const UNSTKERR: u32 = 1 << 11;
+    pub const STKERR: u32 = 1 << 12;
+    pub const LSPERR: u32 = 1 << 13;
+    pub const BFARVALID: u32 = 1 << 15;
+    pub const UNDEFINSTR: u32 = 1 << 16;
+    pub const INVSTATE: u32 = 1 << 17;
+    pub const INVPC: u32 = 1 << 18;
+    pub const NOCP: u32 = 1 << 19;
+    pub const STKOF: u32 = 1 << 20;
+    pub const UNALIGNED: u32 = 1 << 24;
+    pub const DIVBYZERO: u32 = 1 << 25;
+}
+
+mod hfsr {
+    pub const VECTTBL: u32 = 1 << 1;
+    pub const DEBUGEVT: u32 = 1 << 31;
+}
+
+/// Picks the reason code that best describes a Cortex-M fault
+pub fn cortex_m_exception(cfsr: u32, hfsr: u32, pc: u32) -> Exception {
+    use self::cfsr::*;
+
+    if hfsr & hfsr::DEBUGEVT != 0 {
+        Exception::BreakPoint
+    } else if cfsr & (MSTKERR | MUNSTKERR | STKERR | UNSTKERR | STKOF) != 0 {
+        Exception::StackOverflow
+    } else if cfsr & DIVBYZERO != 0 {
+        Exception::DivisionByZero
+    } else if cfsr & UNALIGNED != 0 {
+        Exception::AddressException
+    } else if cfsr & (UNDEFINSTR | INVSTATE | INVPC | NOCP) != 0 {
+        Exception::UndefinedInstr
+    } else if cfsr & (IACCVIOL | IBUSERR) != 0 || hfsr & hfsr::VECTTBL != 0 {
+        match pc {
+            0 => Exception::BranchThroughZero,
+            _ => Exception::PrefetchAbort,
+        }
+    } else if cfsr & (DACCVIOL | MLSPERR | PRECISERR | IMPRECISERR | LSPERR) != 0 {
+        Exception::DataAbort
+    } else {
+        Exception::RunTimeErrorUnknown
+    }
+}
+
+#[test]
+fn cortex_m_exceptions() {
+    assert_eq!(cortex_m_exception(cfsr::DACCVIOL | cfsr::MMARVALID, 0, 0x100), Exception::DataAbort);
+    assert_eq!(cortex_m_exception(cfsr::IACCVIOL, 0, 0), Exception::BranchThroughZero);
+    assert_eq!(cortex_m_exception(cfsr::IBUSERR, 0, 0x100), Exception::PrefetchAbort);
+    assert_eq!(cortex_m_exception(cfsr::STKERR | cfsr::PRECISERR, 0, 0x100), Exception::StackOverflow);
+    assert_eq!(cortex_m_exception(cfsr::UNDEFINSTR, 0, 0x100), Exception::UndefinedInstr);
+    assert_eq!(cortex_m_exception(cfsr::DIVBYZERO, 0, 0x100), Exception::DivisionByZero);
+    assert_eq!(cortex_m_exception(0, hfsr::DEBUGEVT, 0x100), Exception::BreakPoint);
+    assert_eq!(cortex_m_exception(0, 0, 0x100), Exception::RunTimeErrorUnknown);
+}
+
+/// Cortex-M fault status registers
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct FaultStatus {
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: Option<u32>,
+    pub bfar: Option<u32>,
+}
+
+impl FaultStatus {
+    /// Reads the System Control Block, or returns zeroes on baseline cores
+    #[cfg(cortex_m)]
+    pub fn read() -> Self {
+        #[cfg(not(cortex_m_base))]
+        unsafe {
+            use core::ptr::read_volatile;
+
+            let cfsr = read_volatile(0xe000_ed28 as *const u32);
+            Self {
+                cfsr,
+                hfsr: read_volatile(0xe000_ed2c as *const u32),
+                mmfar: if cfsr & cfsr::MMARVALID != 0 { Some(read_volatile(0xe000_ed34 as *const u32)) } else { None },
+                bfar: if cfsr & cfsr::BFARVALID != 0 { Some(read_volatile(0xe000_ed38 as *const u32)) } else { None },
+            }
+        }
+
+        #[cfg(cortex_m_base)]
+        Self::default()
+    }
+}
+
+/// Reports a Cortex-M HardFault to the debugger and exits with the matching reason
+///
+/// `exc_return` is the `lr` value on entry to the handler. cortex-m-rt's
+/// trampoline doesn't pass it on, so without one of your own, give
+/// `0xffff_fffd` on cores that never use the FPU.
+///
+/// ```ignore
+/// #[exception]
+/// unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+///     let frame = &*(frame as *const _ as *const semihosting::fault::ExceptionFrame);
+///     semihosting::fault::hard_fault(frame, 0xffff_fffd)
+/// }
+/// ```
+#[cfg(cortex_m)]
+pub fn hard_fault(frame: &ExceptionFrame, exc_return: u32) -> ! {
+    let status = FaultStatus::read();
+    let exception = cortex_m_exception(status.cfsr, status.hfsr, frame.pc);
+    // a debug event only escalates when no debugger is halting on it, and
+    // semihosting traps would then fault again, so spin in exit_with instead
+    if status.hfsr & hfsr::DEBUGEVT != 0 {
+        crate::set_available(Some(false));
+    }
+    if !crate::is_available() {
+        unsafe { crate::exit_with(exception) }
+    }
+    println!("HardFault: {:?}", exception);
+    print_registers(&frame.registers(exc_return));
+    println!("  cfsr={:#010x} hfsr={:#010x}", status.cfsr, status.hfsr);
+    if let Some(mmfar) = status.mmfar {
+        println!("  mmfar={:#010x}", mmfar);
+    }
+    if let Some(bfar) = status.bfar {
+        println!("  bfar={:#010x}", bfar);
+    }
+    unsafe { crate::exit_with(exception) }
+}
+
+#[cfg(any(arm, thumb))]
+fn print_registers(registers: &Registers) {
+    let r = &registers.r;
+    println!("  r0={:#010x} r1={:#010x} r2={:#010x} r3={:#010x}", r[0], r[1], r[2], r[3]);
+    println!("  r4={:#010x} r5={:#010x} r6={:#010x} r7={:#010x}", r[4], r[5], r[6], r[7]);
+    println!("  r8={:#010x} r9={:#010x} r10={:#010x} r11={:#010x}", r[8], r[9], r[10], r[11]);
+    println!("  r12={:#010x} sp={:#010x} lr={:#010x} pc={:#010x}", r[12], r[13], r[14], r[15]);
+    println!("  psr={:#010x}", registers.psr);
+}
+
+/// Fault status code of a short-descriptor DFSR or IFSR
+fn fault_status_code(fsr: u32) -> u32 {
+    (fsr >> 6 & 0x10) | (fsr & 0xf)
+}
+
+/// Picks the reason code for a Cortex-A/R data abort
+pub fn data_abort_exception(dfsr: u32) -> Exception {
+    match fault_status_code(dfsr) {
+        0b00001 => Exception::AddressException,
+        0b00010 => Exception::WatchPoint,
+        _ => Exception::DataAbort,
+    }
+}
+
+/// Picks the reason code for a Cortex-A/R prefetch abort
+pub fn prefetch_abort_exception(ifsr: u32) -> Exception {
+    match fault_status_code(ifsr) {
+        0b00010 => Exception::BreakPoint,
+        _ => Exception::PrefetchAbort,
+    }
+}
+
+#[test]
+fn abort_exceptions() {
+    assert_eq!(data_abort_exception(0b0001), Exception::AddressException);
+    assert_eq!(data_abort_exception(0b0101), Exception::DataAbort);
+    assert_eq!(data_abort_exception(1 << 10 | 0b0110), Exception::DataAbort);
+    assert_eq!(prefetch_abort_exception(0b0010), Exception::BreakPoint);
+    assert_eq!(prefetch_abort_exception(0b1101), Exception::PrefetchAbort);
+}
+
+/// Reports a Cortex-A/R data abort, reading DFSR and DFAR
+///
+/// `registers` are whatever the abort handler saved, with `pc` pointing at
+/// the faulting instruction.
+#[cfg(all(any(arm, thumb), not(cortex_m)))]
+pub fn data_abort(registers: &Registers) -> ! {
+    let (dfsr, dfar): (u32, u32);
+    unsafe {
+        core::arch::asm!("mrc p15, 0, {}, c5, c0, 0", out(reg) dfsr, options(nomem, nostack, preserves_flags));
+        core::arch::asm!("mrc p15, 0, {}, c6, c0, 0", out(reg) dfar, options(nomem, nostack, preserves_flags));
+    }
+    let exception = data_abort_exception(dfsr);
+    println!("Data abort: {:?}", exception);
+    print_registers(registers);
+    println!("  dfsr={:#010x} dfar={:#010x}", dfsr, dfar);
+    unsafe { crate::exit_with(exception) }
+}
+
+/// Reports a Cortex-A/R prefetch abort, reading IFSR and IFAR
+#[cfg(all(any(arm, thumb), not(cortex_m)))]
+pub fn prefetch_abort(registers: &Registers) -> ! {
+    let (ifsr, ifar): (u32, u32);
+    unsafe {
+        core::arch::asm!("mrc p15, 0, {}, c5, c0, 1", out(reg) ifsr, options(nomem, nostack, preserves_flags));
+        core::arch::asm!("mrc p15, 0, {}, c6, c0, 2", out(reg) ifar, options(nomem, nostack, preserves_flags));
+    }
+    let exception = prefetch_abort_exception(ifsr);
+    println!("Prefetch abort: {:?}", exception);
+    print_registers(registers);
+    println!("  ifsr={:#010x} ifar={:#010x}", ifsr, ifar);
+    unsafe { crate::exit_with(exception) }
+}
+
+/// Reports a Cortex-A/R undefined instruction exception
+#[cfg(all(any(arm, thumb), not(cortex_m)))]
+pub fn undefined_instruction(registers: &Registers) -> ! {
+    println!("Undefined instruction");
+    print_registers(registers);
+    unsafe { crate::exit_with(Exception::UndefinedInstr) }
+}
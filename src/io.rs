@@ -1,8 +1,10 @@
+use core::fmt;
 use core::num::NonZeroUsize;
-use core::mem::{MaybeUninit, forget};
+use core::mem::forget;
 use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use cstrptr::{CStr, CStrPtr};
-use crate::{ syscall, Syscall, Exception, HeapInfo, Mode };
+use crate::{ syscall, Syscall, ReasonCode, HeapInfo, Mode, Extensions };
 
 #[cfg(not(feature = "v2"))]
 pub fn features() -> ! {
@@ -36,6 +38,27 @@ pub enum WriteAllError<E> {
     Invalid, // syscall returned nonsense? this would be an assertion but... don't want to panic!
 }
 
+impl<E: fmt::Display> fmt::Display for WriteAllError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteAllError::Io(e) => write!(f, "I/O error {}", e),
+            WriteAllError::Incomplete(left) => write!(f, "{} bytes left unwritten", left),
+            WriteAllError::Invalid => f.write_str("invalid write result"),
+        }
+    }
+}
+
+#[cfg(feature = "ufmt")]
+impl<E: ufmt::uDisplay> ufmt::uDisplay for WriteAllError<E> {
+    fn fmt<W: ufmt::uWrite + ?Sized>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            WriteAllError::Io(e) => ufmt::uwrite!(f, "I/O error {}", e),
+            WriteAllError::Incomplete(left) => ufmt::uwrite!(f, "{} bytes left unwritten", left),
+            WriteAllError::Invalid => f.write_str("invalid write result"),
+        }
+    }
+}
+
 impl Handle {
     #[inline]
     pub fn open<E: Errno>(path: &CStr, mode: Mode) -> Result<Self, E> {
@@ -60,6 +83,11 @@ impl Handle {
         seek(self.fd.get(), offset).map(drop)
     }
 
+    #[inline]
+    pub fn flen<E: Errno>(&self) -> Result<usize, E> {
+        f_len(self.fd.get())
+    }
+
     #[inline]
     pub fn close<E: Errno>(self) -> Result<(), E> {
         close(self.fd.get())
@@ -90,6 +118,37 @@ impl Handle {
         }
         Ok(())
     }
+
+    /// Writes formatted text, returning the error that `fmt::Error` can't carry
+    ///
+    /// This takes precedence over `fmt::Write::write_fmt`, so it is what
+    /// `write!(handle, ...)` calls.
+    pub fn write_fmt<E: Errno>(&self, args: fmt::Arguments) -> Result<(), WriteAllError<E>> {
+        let mut writer = ErrorWriter {
+            handle: self,
+            error: None,
+        };
+        match fmt::write(&mut writer, args) {
+            Ok(()) => Ok(()),
+            // without an error of our own, a `Display` impl failed
+            Err(fmt::Error) => Err(writer.error.unwrap_or(WriteAllError::Invalid)),
+        }
+    }
+}
+
+/// Remembers the error behind a failed write
+struct ErrorWriter<'a, E> {
+    handle: &'a Handle,
+    error: Option<WriteAllError<E>>,
+}
+
+impl<'a, E: Errno> fmt::Write for ErrorWriter<'a, E> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.handle.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -120,6 +179,12 @@ impl OwnedHandle {
         forget(self);
         handle
     }
+
+    /// See [`Handle::write_fmt`]
+    #[inline]
+    pub fn write_fmt<E: Errno>(&self, args: fmt::Arguments) -> Result<(), WriteAllError<E>> {
+        self.handle.write_fmt(args)
+    }
 }
 
 impl Deref for OwnedHandle {
@@ -136,6 +201,20 @@ impl DerefMut for OwnedHandle {
     }
 }
 
+impl AsRef<Handle> for Handle {
+    #[inline]
+    fn as_ref(&self) -> &Handle {
+        self
+    }
+}
+
+impl AsRef<Handle> for OwnedHandle {
+    #[inline]
+    fn as_ref(&self) -> &Handle {
+        &self.handle
+    }
+}
+
 impl Drop for OwnedHandle {
     fn drop(&mut self) {
         #[cfg(debug_assertions)]
@@ -150,6 +229,215 @@ impl Drop for OwnedHandle {
     }
 }
 
+impl fmt::Write for Handle {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all::<()>(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl fmt::Write for OwnedHandle {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        fmt::Write::write_str(&mut self.handle, s)
+    }
+}
+
+#[cfg(feature = "ufmt-write")]
+impl ufmt_write::uWrite for Handle {
+    type Error = WriteAllError<isize>;
+
+    #[inline]
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.write_all(s.as_bytes())
+    }
+}
+
+#[cfg(feature = "ufmt-write")]
+impl ufmt_write::uWrite for OwnedHandle {
+    type Error = <Handle as ufmt_write::uWrite>::Error;
+
+    #[inline]
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        ufmt_write::uWrite::write_str(&mut self.handle, s)
+    }
+}
+
+/// Seek origin, as in `fseek`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum SeekFrom {
+    Start(usize),
+    End(isize),
+    Current(isize),
+}
+
+impl SeekFrom {
+    /// Resolves to an absolute offset, or `None` if it falls outside of `0..=len`
+    pub fn resolve(self, position: usize, len: usize) -> Option<usize> {
+        fn offset_by(base: usize, offset: isize) -> Option<usize> {
+            if offset < 0 {
+                base.checked_sub(offset.unsigned_abs())
+            } else {
+                base.checked_add(offset as usize)
+            }
+        }
+
+        let offset = match self {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) => offset_by(len, offset)?,
+            SeekFrom::Current(offset) => offset_by(position, offset)?,
+        };
+        if offset <= len {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn seek_resolve() {
+    assert_eq!(SeekFrom::Start(4).resolve(2, 8), Some(4));
+    assert_eq!(SeekFrom::Start(9).resolve(2, 8), None);
+    assert_eq!(SeekFrom::End(0).resolve(2, 8), Some(8));
+    assert_eq!(SeekFrom::End(-3).resolve(2, 8), Some(5));
+    assert_eq!(SeekFrom::End(1).resolve(2, 8), None);
+    assert_eq!(SeekFrom::Current(-2).resolve(2, 8), Some(0));
+    assert_eq!(SeekFrom::Current(-3).resolve(2, 8), None);
+    assert_eq!(SeekFrom::Current(isize::MAX).resolve(usize::MAX, usize::MAX), None);
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum SeekError<E> {
+    Io(E),
+    OutOfBounds,
+}
+
+impl<E: fmt::Display> fmt::Display for SeekError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SeekError::Io(e) => write!(f, "I/O error {}", e),
+            SeekError::OutOfBounds => f.write_str("seek out of bounds"),
+        }
+    }
+}
+
+#[cfg(feature = "ufmt")]
+impl<E: ufmt::uDisplay> ufmt::uDisplay for SeekError<E> {
+    fn fmt<W: ufmt::uWrite + ?Sized>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            SeekError::Io(e) => ufmt::uwrite!(f, "I/O error {}", e),
+            SeekError::OutOfBounds => f.write_str("seek out of bounds"),
+        }
+    }
+}
+
+/// A handle that keeps track of its file position
+///
+/// `SYS_SEEK` only supports absolute offsets, so relative seeks are resolved
+/// against the tracked position and checked against [`f_len`] before they are
+/// issued.
+#[derive(Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct TrackedHandle<H = Handle> {
+    handle: H,
+    position: usize,
+    append: bool,
+}
+
+impl<H> TrackedHandle<H> {
+    /// Assumes the handle is at the start of the file, as it is right after opening
+    #[inline]
+    pub const fn new(handle: H) -> Self {
+        Self::with_position(handle, 0)
+    }
+
+    #[inline]
+    pub const fn with_position(handle: H, position: usize) -> Self {
+        Self {
+            handle,
+            position,
+            append: false,
+        }
+    }
+
+    /// Like [`new`](Self::new), for a handle opened with `mode`
+    ///
+    /// With [`Mode::MODE_APPEND`] the host writes at the end of the file
+    /// whatever the position, so writes move the position there first.
+    #[inline]
+    pub fn with_mode(handle: H, mode: Mode) -> Self {
+        Self {
+            append: mode.contains(Mode::MODE_APPEND),
+            ..Self::new(handle)
+        }
+    }
+
+    #[inline]
+    pub const fn position(&self) -> usize {
+        self.position
+    }
+
+    #[inline]
+    pub const fn get_ref(&self) -> &H {
+        &self.handle
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> H {
+        self.handle
+    }
+}
+
+impl<H: AsRef<Handle>> TrackedHandle<H> {
+    #[inline]
+    pub fn len<E: Errno>(&self) -> Result<usize, E> {
+        self.handle.as_ref().flen()
+    }
+
+    #[inline]
+    pub fn is_empty<E: Errno>(&self) -> Result<bool, E> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Returns the new absolute position
+    pub fn seek<E: Errno>(&mut self, pos: SeekFrom) -> Result<usize, SeekError<E>> {
+        let len = self.len().map_err(SeekError::Io)?;
+        let position = pos.resolve(self.position, len).ok_or(SeekError::OutOfBounds)?;
+        self.handle.as_ref().seek_set(position).map_err(SeekError::Io)?;
+        self.position = position;
+        Ok(position)
+    }
+
+    #[inline]
+    pub fn rewind<E: Errno>(&mut self) -> Result<(), E> {
+        self.handle.as_ref().seek_set(0)?;
+        self.position = 0;
+        Ok(())
+    }
+
+    /// Returns number of bytes that were *not* read
+    #[inline]
+    pub fn read<E: Errno>(&mut self, data: &mut [u8]) -> Result<usize, E> {
+        let left = self.handle.as_ref().read(data)?;
+        self.position += data.len().saturating_sub(left);
+        Ok(left)
+    }
+
+    /// Returns number of bytes that were *not* written
+    #[inline]
+    pub fn write<E: Errno>(&mut self, data: &[u8]) -> Result<usize, E> {
+        if self.append {
+            self.position = self.len()?;
+        }
+        let left = self.handle.as_ref().write(data)?;
+        self.position += data.len().saturating_sub(left);
+        Ok(left)
+    }
+}
+
 pub trait Errno {
     fn last_error() -> Self;
 }
@@ -192,6 +480,14 @@ pub fn tmpnam(id: u8, buffer: &mut [u8]) -> Result<(), ()> {
         .map(drop)
 }
 
+/// Like [`tmpnam`], but returns the name that was written into `buffer`
+#[inline]
+pub fn tmpnam_cstr(id: u8, buffer: &mut [u8]) -> Result<&CStr, ()> {
+    tmpnam(id, buffer)?;
+    let len = buffer.iter().position(|&c| c == 0).ok_or(())?;
+    Ok(unsafe { CStr::from_bytes_with_nul_unchecked(&buffer[..=len]) })
+}
+
 #[inline]
 pub fn remove<E: Errno>(path: &CStr) -> Result<usize, E> {
     let len = path.to_bytes().len(); // NOTE: not guaranteed to be zero-cost
@@ -314,19 +610,85 @@ pub fn write_cstr(str: CStrPtr) {
     unsafe { syscall(Syscall::Write0, str.as_ptr() as usize) };
 }
 
+static EXTENSIONS: AtomicUsize = AtomicUsize::new(0);
+/// Set in `EXTENSIONS` once the host has been asked
+const EXTENSIONS_READ: usize = 1 << 8;
+
+/// Extensions listed in the host's `:semihosting-features` file
+///
+/// The file is read once, and hosts without one support none.
+pub fn extensions() -> Extensions {
+    let cached = EXTENSIONS.load(Ordering::Relaxed);
+    if cached & EXTENSIONS_READ != 0 {
+        return Extensions::from_bits_truncate(cached as u32)
+    }
+    if !crate::is_available() {
+        return Extensions::empty()
+    }
+    let extensions = read_extensions();
+    EXTENSIONS.store(extensions.bits() as usize | EXTENSIONS_READ, Ordering::Relaxed);
+    extensions
+}
+
+fn read_extensions() -> Extensions {
+    let handle = match Handle::open::<()>(cstrptr::cstr!(":semihosting-features"), Mode::BINARY) {
+        Ok(handle) => handle,
+        Err(()) => return Extensions::empty(),
+    };
+    let mut bytes = [0u8; 5];
+    let unread = handle.read::<()>(&mut bytes);
+    let _ = handle.close::<()>();
+    match unread {
+        Ok(0) if &bytes[..4] == crate::MAGIC => Extensions::from_bits_truncate(bytes[4] as u32),
+        _ => Extensions::empty(),
+    }
+}
+
 /// Report an exception or exit condition to the debugger
 ///
 /// Although typically not resumable, the debugger can choose to continue.
+/// The subcode is only passed on by hosts with [`Extensions::EXIT_EXTENDED`].
+/// Otherwise `SYS_EXIT` is used, which only takes the reason on AArch32, so
+/// the subcode is lost.
+#[inline]
+pub fn report_exception<R: Into<ReasonCode>>(reason: R) -> usize {
+    let ReasonCode { exception, subcode } = reason.into();
+    if subcode != 0 && extensions().contains(Extensions::EXIT_EXTENDED) {
+        let res = unsafe { syscall!(Syscall::ReportExceptionExtended, exception, subcode) };
+        if res != core::usize::MAX {
+            return res
+        }
+    }
+    unsafe { syscall(Syscall::ReportException, exception) }
+}
+
+/// Switches the processor to Supervisor mode
+///
+/// Returns the address of a routine that switches back to the original
+/// mode when branched to, or `Err` if the debugger doesn't support this.
+/// Only A/R-profile cores have a Supervisor mode.
+///
+/// # Safety
+///
+/// Execution continues on the Supervisor stack pointer and with the SPSR of
+/// that mode, so it must have been set up beforehand. Locals referenced by
+/// address across the switch will no longer be where the compiler expects
+/// them; ideally the caller does nothing but branch to another function
+/// afterwards. Banked registers such as `lr` are also swapped out.
+#[cfg(all(any(arm, thumb), not(cortex_m)))]
 #[inline]
-pub fn report_exception(reason: Exception) -> usize {
-    unsafe { syscall(Syscall::ReportException, reason) }
+pub unsafe fn enter_svc() -> Result<NonZeroUsize, ()> {
+    NonZeroUsize::new(syscall!(Syscall::EnterSVC)).ok_or(())
 }
 
 #[inline]
 pub fn heapinfo() -> HeapInfo {
-    let mut info = MaybeUninit::uninit();
+    // left zeroed if the call is skipped without a debugger
+    let mut info = HeapInfo::default();
+    // the parameter is the address of a pointer to the block
+    let mut block = &mut info as *mut HeapInfo;
     unsafe {
-        syscall(Syscall::HeapInfo, info.as_mut_ptr() as usize);
-        info.assume_init()
+        syscall(Syscall::HeapInfo, &mut block as *mut *mut HeapInfo as usize);
     }
+    info
 }
@@ -1,6 +1,10 @@
 #![cfg_attr(feature = "unstable", feature(asm, core_intrinsics))]
 #![no_std]
 
+#[cfg(feature = "mock")]
+extern crate std;
+
+use core::convert::TryFrom;
 use core::num::NonZeroU32;
 use cstrptr::CStr;
 
@@ -8,10 +12,27 @@ mod macros;
 #[cfg(all(feature = "ufmt-write", feature = "ufmt"))]
 mod umacros;
 
+#[cfg(any(feature = "trace", feature = "syscall-log"))]
+mod busy;
 mod export;
 mod syscall;
+#[cfg(feature = "coverage")]
+pub mod coverage;
+pub mod dump;
+pub mod event;
+pub mod fault;
 pub mod io;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod path;
 pub mod print;
+pub mod process;
+#[cfg(feature = "syscall-log")]
+pub mod syscall_log;
+pub mod temp;
+pub mod testing;
+#[cfg(feature = "trace")]
+pub mod trace;
 
 #[doc(hidden)]
 pub mod _export {
@@ -47,7 +68,6 @@ pub enum Syscall {
 
     EnterSVC = 23,
     ReportException = 24,
-    #[cfg(feature = "v2")]
     ReportExceptionExtended = 32,
 
     Elapsed = 48,
@@ -81,6 +101,121 @@ pub enum Exception {
     OSSpecific = 0x20029,
 }
 
+impl TryFrom<usize> for Syscall {
+    type Error = ();
+
+    fn try_from(operation: usize) -> Result<Self, Self::Error> {
+        use Syscall::*;
+
+        Ok(match operation {
+            1 => Open,
+            2 => Close,
+            3 => WriteC,
+            4 => Write0,
+            5 => Write,
+            6 => Read,
+            7 => ReadC,
+            8 => IsError,
+            9 => IsTTY,
+            10 => Seek,
+            12 => FLen,
+            13 => TmpNam,
+            14 => Remove,
+            15 => Rename,
+            16 => Clock,
+            17 => Time,
+            18 => System,
+            19 => Errno,
+            21 => GetCmdline,
+            22 => HeapInfo,
+            23 => EnterSVC,
+            24 => ReportException,
+            32 => ReportExceptionExtended,
+            48 => Elapsed,
+            49 => TickFreq,
+            _ => return Err(()),
+        })
+    }
+}
+
+#[test]
+fn syscall_roundtrip() {
+    for operation in 0..64 {
+        if let Ok(syscall) = Syscall::try_from(operation) {
+            assert_eq!(usize::from(syscall), operation);
+        }
+    }
+    assert_eq!(Syscall::try_from(11), Err(()));
+}
+
+impl TryFrom<usize> for Exception {
+    type Error = ();
+
+    fn try_from(reason: usize) -> Result<Self, Self::Error> {
+        use Exception::*;
+
+        Ok(match reason {
+            0x20000 => BranchThroughZero,
+            0x20001 => UndefinedInstr,
+            0x20002 => SoftwareInterrupt,
+            0x20003 => PrefetchAbort,
+            0x20004 => DataAbort,
+            0x20005 => AddressException,
+            0x20006 => IRQ,
+            0x20007 => FIQ,
+            0x20020 => BreakPoint,
+            0x20021 => WatchPoint,
+            0x20022 => StepComplete,
+            0x20023 => RunTimeErrorUnknown,
+            0x20024 => InternalError,
+            0x20025 => UserInterruption,
+            0x20026 => ApplicationExit,
+            0x20027 => StackOverflow,
+            0x20028 => DivisionByZero,
+            0x20029 => OSSpecific,
+            _ => return Err(()),
+        })
+    }
+}
+
+#[test]
+fn exception_roundtrip() {
+    for reason in (0x20000..0x20008).chain(0x20020..0x2002a) {
+        assert_eq!(Exception::try_from(reason).map(usize::from), Ok(reason));
+    }
+    assert_eq!(Exception::try_from(0x20008), Err(()));
+    assert_eq!(Exception::try_from(0), Err(()));
+}
+
+/// An exception reason along with its subcode
+///
+/// The subcode is passed with `SYS_EXIT_EXTENDED`, and for
+/// [`Exception::ApplicationExit`] is the exit status. A zero subcode falls
+/// back to a plain `SYS_EXIT`, which hosts without the extension support.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct ReasonCode {
+    pub exception: Exception,
+    pub subcode: usize,
+}
+
+impl ReasonCode {
+    #[inline]
+    pub const fn new(exception: Exception, subcode: usize) -> Self {
+        Self {
+            exception,
+            subcode,
+        }
+    }
+}
+
+impl From<Exception> for ReasonCode {
+    #[inline]
+    fn from(exception: Exception) -> Self {
+        Self::new(exception, 0)
+    }
+}
+
 impl From<Syscall> for usize {
     fn from(s: Syscall) -> Self {
         s as _
@@ -107,6 +242,7 @@ bitflags::bitflags! {
     pub struct Mode: u32 {
         const BINARY = 1;
         const MODE_READ_ONLY = 0;
+        const MODE_UPDATE = 2; // "+"
         const MODE_READ_WRITE = 4;
         const MODE_APPEND = 8;
     }
@@ -119,10 +255,13 @@ bitflags::bitflags! {
     }
 }
 
-const MAGIC: &'static [u8] = b"SHFB"; // TODO check endianness
+/// Start of the `:semihosting-features` file, followed by [`Extensions`] bytes
+pub const MAGIC: &'static [u8] = b"SHFB"; // TODO check endianness
 
-pub use syscall::{syscall, syscall0};
-pub use print::{CharPrinter, GlobalLogger, LOGGER, print_str, print_cstr, print_char};
+pub use syscall::{syscall, syscall0, is_available, set_available};
+pub use print::{CharPrinter, Destination, GlobalLogger, LOGGER, Sink, print_str, print_cstr, print_char};
+#[cfg(target_has_atomic = "ptr")]
+pub use print::set_sink;
 
 /// Normal application exit
 ///
@@ -138,13 +277,43 @@ pub fn abort() -> ! {
     unsafe { exit_with(Exception::InternalError) } // or RunTimeErrorUnknown? OSSpecific?
 }
 
+/// Stops in the debugger as if a breakpoint was hit
+///
+/// Unlike [`exit_with`], this returns once the debugger resumes.
+#[inline]
+pub fn breakpoint() {
+    io::report_exception(Exception::BreakPoint);
+}
+
+/// Stops in the debugger as if a watchpoint was hit, returning on resume
+#[inline]
+pub fn watchpoint_hit() {
+    io::report_exception(Exception::WatchPoint);
+}
+
+/// Stops in the debugger as if a single step completed, returning on resume
+#[inline]
+pub fn step_complete() {
+    io::report_exception(Exception::StepComplete);
+}
+
 /// Abort with the given exception reason
 #[inline]
-pub unsafe fn exit_with(exception: Exception) -> ! {
+pub unsafe fn exit_with<R: Into<ReasonCode>>(reason: R) -> ! {
+    let reason = reason.into();
+
+    #[cfg(feature = "coverage")]
+    coverage::dump_on_exit();
+    #[cfg(feature = "syscall-log")]
+    syscall_log::dump_on_exit();
+
     loop {
-        io::report_exception(exception);
-        #[cfg(not(debug_assertions))]
-        core::hint::unreachable_unchecked();
+        // the debugger may choose to resume, in which case we ask again
+        if is_available() {
+            io::report_exception(reason);
+        } else {
+            core::hint::spin_loop();
+        }
     }
 }
 
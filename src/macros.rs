@@ -3,9 +3,8 @@
 macro_rules! print_fmt {
     ($($tt:tt)*) => {
         {
-            use $crate::_export::core::fmt::Write;
             // failures aren't interesting to us
-            let _ = $crate::_export::core::write!(&$crate::LOGGER, $($tt)*);
+            let _ = $crate::print::Sink::write_fmt($crate::print::sink(), $crate::_export::core::format_args!($($tt)*));
         }
     };
 }
@@ -13,56 +12,36 @@ macro_rules! print_fmt {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! println_fmt {
-    ($($tt:tt)*) => {
-        {
-            use $crate::_export::core::fmt::Write;
-            // failures aren't interesting to us
-            let _ = $crate::_export::core::writeln!(&$crate::LOGGER, $($tt)*);
-        }
+    () => {
+        $crate::print_str!("\n")
+    };
+    ($fmt:literal $($tt:tt)*) => {
+        $crate::print_fmt!($crate::_export::core::concat!($fmt, "\n") $($tt)*)
     };
 }
 
 #[doc(hidden)]
-#[cfg(feature = "enable-logger")]
 #[macro_export]
 macro_rules! print_str {
-    ($str:expr) => {
+    ($str:literal) => {
         {
-            let _ = $crate::_export::core::fmt::Write::write_str(&mut &$crate::LOGGER, $str);
+            let _ = $crate::print::Sink::write_cstr($crate::print::sink(), $crate::_export::cstr!($str));
         }
     };
-}
-
-#[doc(hidden)]
-#[cfg(feature = "enable-logger")]
-#[macro_export]
-macro_rules! println_str {
     ($str:expr) => {
         {
-            let _ = $crate::_export::core::fmt::Write::write_str(&mut &$crate::LOGGER, $str);
-            let _ = $crate::_export::core::fmt::Write::write_str(&mut &$crate::LOGGER, "\n");
+            let _ = $crate::print::Sink::write_str($crate::print::sink(), $str);
         }
     };
 }
 
 #[doc(hidden)]
-#[cfg(not(feature = "enable-logger"))]
-#[macro_export]
-macro_rules! print_str {
-    ($str:literal) => {
-        $crate::print_cstr($crate::_export::cstr!($str).into())
-    };
-    ($str:expr) => {
-        $crate::print_fmt!("{}", $str)
-    };
-}
-
-#[doc(hidden)]
-#[cfg(not(feature = "enable-logger"))]
 #[macro_export]
 macro_rules! println_str {
     ($str:literal) => {
-        $crate::print_cstr($crate::_export::cstr!(concat!($str, "\n")).into())
+        {
+            let _ = $crate::print::Sink::write_cstr($crate::print::sink(), $crate::_export::cstr!($crate::_export::core::concat!($str, "\n")));
+        }
     };
     ($str:expr) => {
         $crate::println_fmt!("{}", $str)
@@ -94,3 +73,77 @@ macro_rules! println {
         $crate::println_fmt!($($tt)*)
     };
 }
+
+/// Runs the listed test functions with [`testing::runner`](crate::testing::runner)
+///
+/// A stable alternative to `custom_test_frameworks`:
+///
+/// ```ignore
+/// #[entry]
+/// fn main() -> ! {
+///     semihosting::test_main!(tests::open, tests::seek)
+/// }
+/// ```
+#[macro_export]
+macro_rules! test_main {
+    ($($test:path),* $(,)?) => {
+        $crate::testing::runner(&[$(
+            &$crate::testing::TestCase::new($crate::_export::core::stringify!($test), $test) as &dyn $crate::testing::Testable
+        ),*])
+    };
+}
+
+/// Records entry to `function` now, and its exit at the end of the enclosing scope
+///
+/// ```ignore
+/// fn parse(input: &[u8]) {
+///     semihosting::trace!(parse);
+///     ...
+/// }
+/// ```
+#[cfg(feature = "trace")]
+#[macro_export]
+macro_rules! trace {
+    ($function:path) => {
+        let _trace_guard = $crate::trace::Guard::enter($function as usize);
+    };
+}
+
+/// Like `assert!`, but stops in the debugger on failure instead of panicking
+///
+/// The failed condition is printed before calling [`breakpoint`](crate::breakpoint),
+/// and execution carries on if the debugger resumes.
+#[macro_export]
+macro_rules! assert_or_break {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            $crate::println_str!($crate::_export::core::concat!("assertion failed: ", $crate::_export::core::stringify!($cond)));
+            $crate::breakpoint();
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::print_str!($crate::_export::core::concat!("assertion failed: ", $crate::_export::core::stringify!($cond), ": "));
+            $crate::println!($($arg)+);
+            $crate::breakpoint();
+        }
+    };
+}
+
+/// Logs an event with an id and named fields to the file opened by
+/// [`event::start`](crate::event::start)
+///
+/// ```ignore
+/// semihosting::event!(3, temperature = reading, channel = 1u8);
+/// ```
+#[macro_export]
+macro_rules! event {
+    ($id:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        {
+            // failures aren't interesting to us
+            let _ = $crate::event::Event::new($id)
+                $(.field($crate::_export::core::stringify!($key), &$value))*
+                .emit::<()>();
+        }
+    };
+}
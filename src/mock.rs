@@ -0,0 +1,278 @@
+//! A scripted stand-in for the debugger, for unit testing on the host
+//!
+//! With the `mock` feature, every semihosting call is recorded instead of
+//! trapping, and answered from replies queued with [`expect`]. State is
+//! per-thread, so tests can run in parallel.
+//!
+//! ```ignore
+//! mock::expect(Syscall::Write, Reply::Return(5));
+//! assert!(matches!(handle.write_all::<()>(b"hello"), Err(WriteAllError::Incomplete(5))));
+//! assert_eq!(mock::calls()[0].data, b"hello");
+//! ```
+
+use core::convert::TryFrom;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::slice;
+use std::vec::Vec;
+use crate::{Exception, Syscall};
+
+/// A scripted result for the next call of an operation
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Reply {
+    /// Return this value as is
+    Return(usize),
+    /// Fail with `usize::MAX`, and report this from `SYS_ERRNO`
+    Error(isize),
+    /// Fill the call's buffer with this, for `SYS_READ`, `SYS_READC`,
+    /// `SYS_GET_CMDLINE`, `SYS_TMPNAM` and `SYS_HEAPINFO`
+    Data(Vec<u8>),
+}
+
+/// A recorded call with its parameter block decoded
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Call {
+    pub syscall: Syscall,
+    /// Words of the parameter block, or the parameter itself where it isn't a pointer
+    pub args: Vec<usize>,
+    /// The path, command or data passed to the host
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    script: VecDeque<(Syscall, Reply)>,
+    calls: Vec<Call>,
+    errno: isize,
+    next_fd: usize,
+}
+
+std::thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+/// Queues a reply for the next call of `syscall`
+///
+/// Replies are used in order for each operation. Operations without a queued
+/// reply succeed, reading nothing and writing everything.
+pub fn expect(syscall: Syscall, reply: Reply) {
+    STATE.with(|state| state.borrow_mut().script.push_back((syscall, reply)));
+}
+
+/// Takes the calls recorded so far
+pub fn calls() -> Vec<Call> {
+    STATE.with(|state| std::mem::take(&mut state.borrow_mut().calls))
+}
+
+/// Forgets recorded calls, queued replies and errno
+pub fn reset() {
+    STATE.with(|state| *state.borrow_mut() = State::default());
+}
+
+unsafe fn bytes(ptr: usize, len: usize) -> Vec<u8> {
+    slice::from_raw_parts(ptr as *const u8, len).to_vec()
+}
+
+unsafe fn decode(syscall: Syscall, parameter: usize) -> Call {
+    let args = match crate::syscall::block_len(syscall) {
+        0 => Vec::new(),
+        len => slice::from_raw_parts(parameter as *const usize, len).to_vec(),
+    };
+    let data = match syscall {
+        Syscall::Open => bytes(args[0], args[2]),
+        Syscall::Remove | Syscall::System | Syscall::Rename => bytes(args[0], args[1]),
+        Syscall::Write => bytes(args[1], args[2]),
+        Syscall::WriteC => bytes(parameter, 1),
+        Syscall::Write0 => CStr::from_ptr(parameter as *const _).to_bytes().to_vec(),
+        _ => Vec::new(),
+    };
+    let args = match syscall {
+        Syscall::ReportException => std::vec![parameter],
+        _ => args,
+    };
+    Call {
+        syscall,
+        args,
+        data,
+    }
+}
+
+/// Copies `data` into a buffer of `len` bytes, returning how much fit
+unsafe fn fill(ptr: usize, len: usize, data: &[u8]) -> usize {
+    let n = data.len().min(len);
+    slice::from_raw_parts_mut(ptr as *mut u8, n).copy_from_slice(&data[..n]);
+    n
+}
+
+/// Answers a call without a scripted reply
+unsafe fn default_reply(state: &mut State, call: &Call, parameter: usize) -> usize {
+    match call.syscall {
+        Syscall::Open => {
+            state.next_fd += 1;
+            state.next_fd
+        },
+        Syscall::Read => call.args[2],
+        Syscall::IsError => ((call.args[0] as isize) < 0) as usize,
+        Syscall::IsTTY => 0,
+        Syscall::Errno => state.errno as usize,
+        // no heap is described, and no time has passed
+        Syscall::HeapInfo => {
+            std::ptr::write_bytes(*(parameter as *const usize) as *mut u8, 0, std::mem::size_of::<crate::HeapInfo>());
+            0
+        },
+        Syscall::Elapsed => {
+            std::ptr::write_bytes(parameter as *mut u8, 0, 8);
+            0
+        },
+        Syscall::GetCmdline => {
+            *(parameter as *mut usize).add(1) = 0;
+            fill(call.args[0], call.args[1], b"\0");
+            0
+        },
+        Syscall::ReportException | Syscall::ReportExceptionExtended => {
+            let reason = call.args[0];
+            match Exception::try_from(reason) {
+                Ok(Exception::BreakPoint) | Ok(Exception::WatchPoint) | Ok(Exception::StepComplete) => 0,
+                Ok(exception) => panic!("semihosting exit: {:?} {:?}", exception, call.args.get(1)),
+                Err(()) => panic!("semihosting exit: {:#x}", reason),
+            }
+        },
+        _ => 0,
+    }
+}
+
+unsafe fn data_reply(call: &Call, parameter: usize, data: &[u8]) -> usize {
+    match call.syscall {
+        Syscall::Read => call.args[2] - fill(call.args[1], call.args[2], data),
+        Syscall::ReadC => data.first().copied().map_or(usize::MAX, usize::from),
+        // the parameter points to a pointer to the block
+        Syscall::HeapInfo => {
+            fill(*(parameter as *const usize), std::mem::size_of::<crate::HeapInfo>(), data);
+            0
+        },
+        Syscall::GetCmdline | Syscall::TmpNam => {
+            let len = if call.syscall == Syscall::TmpNam { call.args[2] } else { call.args[1] };
+            let written = fill(call.args[0], len.saturating_sub(1), data);
+            fill(call.args[0] + written, 1, b"\0");
+            if call.syscall == Syscall::GetCmdline {
+                *(parameter as *mut usize).add(1) = written;
+            }
+            0
+        },
+        syscall => panic!("no data reply for {:?}", syscall),
+    }
+}
+
+pub(crate) unsafe fn syscall(operation: usize, parameter: usize) -> usize {
+    let syscall = match Syscall::try_from(operation) {
+        Ok(syscall) => syscall,
+        Err(()) => panic!("unknown semihosting operation {:#x}", operation),
+    };
+    let call = decode(syscall, parameter);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let reply = state.script.iter().position(|&(s, _)| s == syscall)
+            .and_then(|i| state.script.remove(i))
+            .map(|(_, reply)| reply);
+        let res = match reply {
+            None => default_reply(&mut state, &call, parameter),
+            Some(Reply::Return(res)) => res,
+            Some(Reply::Error(errno)) => {
+                state.errno = errno;
+                usize::MAX
+            },
+            Some(Reply::Data(data)) => data_reply(&call, parameter, &data),
+        };
+        state.calls.push(call);
+        res
+    })
+}
+
+#[test]
+fn mock_write_all() {
+    use crate::io::{Handle, WriteAllError};
+
+    reset();
+    let handle = Handle::from_fd(core::num::NonZeroUsize::new(3).unwrap());
+    expect(Syscall::Write, Reply::Return(2));
+    assert!(handle.write_all::<()>(b"hello").is_ok());
+    expect(Syscall::Write, Reply::Return(5));
+    assert!(matches!(handle.write_all::<()>(b"hello"), Err(WriteAllError::Incomplete(5))));
+    expect(Syscall::Write, Reply::Error(28));
+    assert!(matches!(handle.write_all::<isize>(b"hello"), Err(WriteAllError::Io(28))));
+
+    let calls = calls();
+    let writes: Vec<&[u8]> = calls.iter().filter(|c| c.syscall == Syscall::Write).map(|c| &c.data[..]).collect();
+    assert_eq!(writes, [&b"hello"[..], b"lo", b"hello", b"hello"]);
+    assert_eq!(calls.last().unwrap().syscall, Syscall::Errno);
+}
+
+#[test]
+fn mock_open_read() {
+    use crate::io::Handle;
+    use crate::Mode;
+
+    reset();
+    let path = cstrptr::cstr!("data.bin");
+    let handle = Handle::open::<()>(path, Mode::MODE_READ_ONLY).unwrap();
+    assert_eq!(handle.fd().get(), 1);
+    expect(Syscall::Open, Reply::Error(2));
+    assert_eq!(Handle::open::<isize>(path, Mode::MODE_READ_ONLY).map(|h| h.fd()), Err(2));
+
+    let mut buffer = [0u8; 8];
+    expect(Syscall::Read, Reply::Data(b"abc".to_vec()));
+    assert_eq!(handle.read::<()>(&mut buffer), Ok(5));
+    assert_eq!(&buffer[..3], b"abc");
+
+    let calls = calls();
+    assert_eq!(calls[0], Call {
+        syscall: Syscall::Open,
+        args: std::vec![calls[0].args[0], 0, 8],
+        data: b"data.bin".to_vec(),
+    });
+    assert_eq!(calls[3].args[1..], [buffer.as_ptr() as usize, 8]);
+}
+
+#[test]
+#[should_panic(expected = "ApplicationExit")]
+fn mock_exit() {
+    crate::exit()
+}
+
+#[test]
+fn mock_heapinfo() {
+    reset();
+    let info = crate::io::heapinfo();
+    assert_eq!(std::format!("{:?}", info), "HeapInfo { heap_base: None, heap_limit: None, stack_base: None, stack_limit: None }");
+    assert_eq!(calls()[0].syscall, Syscall::HeapInfo);
+
+    // written through the pointer at the parameter, as hosts do
+    let words: Vec<u8> = [0x2000_0000u32, 0x2000_8000, 0x2001_0000, 0x2000_c000].iter()
+        .flat_map(|word| word.to_ne_bytes().to_vec())
+        .collect();
+    expect(Syscall::HeapInfo, Reply::Data(words));
+    let info = crate::io::heapinfo();
+    assert_eq!(std::format!("{:?}", info), "HeapInfo { heap_base: Some(536870912), heap_limit: Some(536903680), stack_base: Some(536936448), stack_limit: Some(536920064) }");
+}
+
+#[test]
+fn mock_tracked_append() {
+    use crate::io::{Handle, TrackedHandle};
+    use crate::Mode;
+
+    reset();
+    let handle = Handle::from_fd(core::num::NonZeroUsize::new(3).unwrap());
+    let mut file = TrackedHandle::with_mode(handle, Mode::MODE_APPEND | Mode::MODE_UPDATE);
+    let mut buffer = [0u8; 4];
+    expect(Syscall::Read, Reply::Data(b"abcd".to_vec()));
+    assert_eq!(file.read::<()>(&mut buffer), Ok(0));
+    assert_eq!(file.position(), 4);
+
+    // the host appends after the 10 bytes already in the file
+    expect(Syscall::FLen, Reply::Return(10));
+    assert_eq!(file.write::<()>(b"xy"), Ok(0));
+    assert_eq!(file.position(), 12);
+    expect(Syscall::FLen, Reply::Return(0));
+    assert_eq!(file.is_empty::<()>(), Ok(true));
+}
@@ -0,0 +1,280 @@
+use core::fmt;
+use core::ops::Deref;
+use core::convert::TryFrom;
+use cstrptr::CStr;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum PathError {
+    /// The path and its nul terminator do not fit in the buffer
+    TooLong,
+    InteriorNul,
+}
+
+/// A fixed-capacity, nul-terminated host path
+///
+/// Holds up to `N - 1` bytes and derefs to [`CStr`], so paths built at runtime
+/// can be passed straight to [`io::open`](crate::io::open) and friends.
+/// Failed operations leave the path unchanged.
+///
+/// `N` counts the terminator, so `PathBuf<0>` is rejected at compile time.
+#[derive(Clone)]
+pub struct PathBuf<const N: usize = 64> {
+    buffer: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> PathBuf<N> {
+    const NONEMPTY: () = assert!(N > 0, "PathBuf needs room for the nul terminator");
+
+    #[inline]
+    pub const fn new() -> Self {
+        let () = Self::NONEMPTY;
+        Self {
+            buffer: [0; N],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        N.saturating_sub(1)
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        // only ever appended to from &str
+        unsafe { core::str::from_utf8_unchecked(self.as_bytes()) }
+    }
+
+    #[inline]
+    pub fn as_cstr(&self) -> &CStr {
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.buffer[..=self.len]) }
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.truncate(0)
+    }
+
+    /// Appends `s` as-is, without adding a separator
+    pub fn push_str(&mut self, s: &str) -> Result<(), PathError> {
+        let bytes = s.as_bytes();
+        if bytes.contains(&0) {
+            return Err(PathError::InteriorNul)
+        }
+        let end = self.len.checked_add(bytes.len()).filter(|&end| end < N).ok_or(PathError::TooLong)?;
+        self.buffer[self.len..end].copy_from_slice(bytes);
+        self.truncate(end);
+        Ok(())
+    }
+
+    /// Appends a path component, adding a `/` separator if needed
+    ///
+    /// An absolute `component` replaces the whole path.
+    pub fn push(&mut self, component: &str) -> Result<(), PathError> {
+        let absolute = component.starts_with('/');
+        let start = if absolute { 0 } else { self.len };
+        let separator = !absolute && self.needs_separator();
+        if component.as_bytes().contains(&0) {
+            return Err(PathError::InteriorNul)
+        }
+        if start + separator as usize + component.len() >= N {
+            return Err(PathError::TooLong)
+        }
+
+        self.truncate(start);
+        if separator {
+            self.push_str("/")?;
+        }
+        self.push_str(component)
+    }
+
+    /// Appends a formatted path component, adding a `/` separator if needed
+    ///
+    /// ```ignore
+    /// path.push_fmt(format_args!("test_{}.bin", 42))?;
+    /// ```
+    pub fn push_fmt(&mut self, args: fmt::Arguments) -> Result<(), PathError> {
+        let len = self.len;
+        if self.needs_separator() {
+            self.push_str("/")?;
+        }
+        let mut writer = PathWriter {
+            path: self,
+            error: None,
+        };
+        if fmt::write(&mut writer, args).is_err() {
+            let error = writer.error.unwrap_or(PathError::TooLong);
+            self.truncate(len);
+            return Err(error)
+        }
+        Ok(())
+    }
+
+    /// Removes the last component, returning `false` if there was none
+    pub fn pop(&mut self) -> bool {
+        let bytes = self.as_bytes();
+        // a trailing separator doesn't count as a component, but the root does
+        let bytes = match bytes {
+            [rest @ .., b'/'] if !rest.is_empty() => rest,
+            bytes => bytes,
+        };
+        if bytes.is_empty() || bytes == b"/" {
+            return false
+        }
+        let len = match bytes.iter().rposition(|&c| c == b'/') {
+            Some(0) => 1,
+            Some(sep) => sep,
+            None => 0,
+        };
+        self.truncate(len);
+        true
+    }
+
+    #[inline]
+    fn needs_separator(&self) -> bool {
+        !self.is_empty() && !self.as_bytes().ends_with(b"/")
+    }
+
+    #[inline]
+    fn truncate(&mut self, len: usize) {
+        self.len = len;
+        self.buffer[len] = 0;
+    }
+}
+
+struct PathWriter<'a, const N: usize> {
+    path: &'a mut PathBuf<N>,
+    error: Option<PathError>,
+}
+
+impl<'a, const N: usize> fmt::Write for PathWriter<'a, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.path.push_str(s).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+impl<const N: usize> Default for PathBuf<N> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize> TryFrom<&'a str> for PathBuf<N> {
+    type Error = PathError;
+
+    fn try_from(path: &'a str) -> Result<Self, Self::Error> {
+        let mut buf = Self::new();
+        buf.push_str(path)?;
+        Ok(buf)
+    }
+}
+
+impl<const N: usize> Deref for PathBuf<N> {
+    type Target = CStr;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.as_cstr()
+    }
+}
+
+impl<const N: usize> AsRef<CStr> for PathBuf<N> {
+    #[inline]
+    fn as_ref(&self) -> &CStr {
+        self.as_cstr()
+    }
+}
+
+impl<const N: usize> fmt::Debug for PathBuf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> fmt::Display for PathBuf<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Appends without a separator
+impl<const N: usize> fmt::Write for PathBuf<N> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(feature = "ufmt-write")]
+impl<const N: usize> ufmt_write::uWrite for PathBuf<N> {
+    type Error = PathError;
+
+    #[inline]
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.push_str(s)
+    }
+}
+
+#[test]
+fn path_push() {
+    let mut path = PathBuf::<32>::try_from("results").unwrap();
+    path.push_fmt(format_args!("test_{}.bin", 42)).unwrap();
+    assert_eq!(path.as_str(), "results/test_42.bin");
+    assert_eq!(path.as_cstr().to_bytes(), b"results/test_42.bin");
+
+    path.push("/tmp").unwrap();
+    assert_eq!(path.as_str(), "/tmp");
+    path.push("log").unwrap();
+    assert_eq!(path.as_str(), "/tmp/log");
+
+    assert!(path.pop());
+    assert_eq!(path.as_str(), "/tmp");
+    assert!(path.pop());
+    assert_eq!(path.as_str(), "/");
+    assert!(!path.pop());
+}
+
+#[test]
+fn path_errors() {
+    let mut path = PathBuf::<8>::try_from("dir").unwrap();
+    assert_eq!(path.push("file"), Err(PathError::TooLong));
+    assert_eq!(path.push_fmt(format_args!("{}", 1234)), Err(PathError::TooLong));
+    assert_eq!(path.push("a\0b"), Err(PathError::InteriorNul));
+    assert_eq!(path.as_str(), "dir");
+    path.push("abc").unwrap();
+    assert_eq!(path.as_str(), "dir/abc");
+    assert_eq!(path.len(), path.capacity());
+}
+
+#[test]
+fn path_only_terminator() {
+    let mut path = PathBuf::<1>::new();
+    assert_eq!(path.capacity(), 0);
+    assert_eq!(path.as_cstr().to_bytes(), b"");
+    assert_eq!(path.push_str("a"), Err(PathError::TooLong));
+    assert_eq!(path.push("a"), Err(PathError::TooLong));
+    assert!(!path.pop());
+    path.clear();
+    assert!(path.is_empty());
+}
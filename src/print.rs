@@ -1,7 +1,10 @@
 use core::fmt;
 use core::num::NonZeroUsize;
+#[cfg(feature = "critical-section")]
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::{AtomicUsize, Ordering};
-use crate::io::{Handle, OwnedHandle, write_char, open};
+use cstrptr::CStr;
+use crate::io::{Errno, Handle, OwnedHandle, WriteAllError, write_char, open};
 use crate::Mode;
 use cstrptr::cstr;
 
@@ -44,9 +47,156 @@ impl<'a> ufmt_write::uWrite for &'a CharPrinter {
     }
 }
 
+/// An output for the crate's `print!` family of macros
+///
+/// The macros write to [`LOGGER`] until [`set_sink`] is called, so libraries
+/// can print without choosing a backend for the application.
+pub trait Sink: Sync {
+    fn write_str(&self, s: &str) -> fmt::Result;
+
+    /// Writes a whole formatted message, which sinks may choose to keep together
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        fmt::write(&mut SinkWriter(self), args)
+    }
+
+    /// Writes a string literal, which is also available nul-terminated
+    #[inline]
+    fn write_cstr(&self, s: &CStr) -> fmt::Result {
+        let s = core::str::from_utf8(s.to_bytes()).map_err(|_| fmt::Error)?;
+        self.write_str(s)
+    }
+}
+
+/// Adapts a [`Sink`] to `fmt::Write` and `uWrite`
+pub struct SinkWriter<'a, S: ?Sized = dyn Sink>(pub &'a S);
+
+impl<'a, S: Sink + ?Sized> fmt::Write for SinkWriter<'a, S> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s)
+    }
+}
+
+#[cfg(feature = "ufmt-write")]
+impl<'a, S: Sink + ?Sized> ufmt_write::uWrite for SinkWriter<'a, S> {
+    type Error = fmt::Error;
+
+    #[inline]
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
+        self.0.write_str(s)
+    }
+}
+
+impl Sink for CharPrinter {
+    #[inline]
+    fn write_str(&self, s: &str) -> fmt::Result {
+        print_str(s);
+        Ok(())
+    }
+
+    #[inline]
+    fn write_cstr(&self, s: &CStr) -> fmt::Result {
+        print_cstr(s.into());
+        Ok(())
+    }
+}
+
+static mut SINK: &dyn Sink = &LOGGER;
+static SINK_STATE: AtomicUsize = AtomicUsize::new(SINK_UNSET);
+const SINK_UNSET: usize = 0;
+const SINK_SETTING: usize = 1;
+const SINK_SET: usize = 2;
+
+/// Routes the `print!` family of macros to `sink`
+///
+/// This can only be done once, and fails if a sink was already set.
+#[cfg(target_has_atomic = "ptr")]
+pub fn set_sink(sink: &'static dyn Sink) -> Result<(), ()> {
+    match SINK_STATE.compare_exchange(SINK_UNSET, SINK_SETTING, Ordering::Acquire, Ordering::Relaxed) {
+        Ok(_) => {
+            unsafe { SINK = sink };
+            SINK_STATE.store(SINK_SET, Ordering::Release);
+            Ok(())
+        },
+        Err(_) => Err(()),
+    }
+}
+
+/// Like [`set_sink`], for targets without compare-and-swap
+///
+/// # Safety
+///
+/// Nothing else may print or set the sink concurrently, so this is best
+/// called during initialization before interrupts are enabled.
+pub unsafe fn set_sink_racy(sink: &'static dyn Sink) -> Result<(), ()> {
+    match SINK_STATE.load(Ordering::Acquire) {
+        SINK_UNSET => {
+            SINK = sink;
+            SINK_STATE.store(SINK_SET, Ordering::Release);
+            Ok(())
+        },
+        _ => Err(()),
+    }
+}
+
+/// The sink currently used by the `print!` family of macros
+#[inline]
+pub fn sink() -> &'static dyn Sink {
+    match SINK_STATE.load(Ordering::Acquire) {
+        SINK_SET => unsafe { SINK },
+        _ => &LOGGER,
+    }
+}
+
+/// Bytes passed to each `SYS_WRITE0`, including the nul terminator
+const WRITE0_LEN: usize = 64;
+
+/// Splits a string into runs that `SYS_WRITE0` can print
+///
+/// Interior nuls can't be part of a run, so each is yielded on its own.
+struct Write0Chunks<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for Write0Chunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None
+        }
+        let len = match self.bytes.iter().take(WRITE0_LEN - 1).position(|&b| b == 0) {
+            Some(0) => 1,
+            Some(nul) => nul,
+            None => self.bytes.len().min(WRITE0_LEN - 1),
+        };
+        let (chunk, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(chunk)
+    }
+}
+
+#[test]
+fn write0_chunks() {
+    let chunks = |bytes: &'static [u8]| Write0Chunks { bytes };
+    assert_eq!(chunks(b"").count(), 0);
+    assert!(chunks(b"hello").eq([&b"hello"[..]].iter().copied()));
+    assert!(chunks(b"a\0\0b").eq([&b"a"[..], b"\0", b"\0", b"b"].iter().copied()));
+    assert!(chunks(&[b'x'; 128]).map(|c| c.len()).eq([63, 63, 2].iter().copied()));
+}
+
+/// Prints a string with one `SYS_WRITE0` per 63 bytes
 pub fn print_str(str: &str) {
-    for c in str.as_bytes() {
-        write_char(*c)
+    let mut buffer = [0u8; WRITE0_LEN];
+    for chunk in (Write0Chunks { bytes: str.as_bytes() }) {
+        if chunk == b"\0" {
+            write_char(0);
+            continue
+        }
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        buffer[chunk.len()] = 0;
+        let cstr = unsafe { CStr::from_bytes_with_nul_unchecked(&buffer[..=chunk.len()]) };
+        print_cstr(cstr.into());
     }
 }
 
@@ -64,8 +214,61 @@ pub static LOGGER: GlobalLogger = GlobalLogger::new();
 #[cfg(not(feature = "enable-logger"))]
 pub static LOGGER: CharPrinter = CharPrinter;
 
+/// Where [`GlobalLogger`] sends messages
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum Destination {
+    /// The debugger console, through a lazily opened `:tt` handle
+    Console,
+    /// Only the given file
+    File(Handle),
+    /// Both the console and the given file
+    Tee(Handle),
+}
+
+impl Destination {
+    // packed into a single word as `fd << 1 | tee`
+    fn encode(self) -> usize {
+        match self {
+            Destination::Console => 0,
+            Destination::File(handle) => handle.fd().get() << 1,
+            Destination::Tee(handle) => handle.fd().get() << 1 | 1,
+        }
+    }
+
+    fn decode(value: usize) -> Self {
+        match NonZeroUsize::new(value >> 1) {
+            None => Destination::Console,
+            Some(fd) if value & 1 == 0 => Destination::File(Handle::from_fd(fd)),
+            Some(fd) => Destination::Tee(Handle::from_fd(fd)),
+        }
+    }
+}
+
+#[test]
+fn destination_encoding() {
+    let fd = NonZeroUsize::new(3).unwrap();
+    assert!(matches!(Destination::decode(Destination::Console.encode()), Destination::Console));
+    assert!(matches!(Destination::decode(Destination::File(Handle::from_fd(fd)).encode()), Destination::File(h) if h.fd() == fd));
+    assert!(matches!(Destination::decode(Destination::Tee(Handle::from_fd(fd)).encode()), Destination::Tee(h) if h.fd() == fd));
+}
+
+/// Writes to the debugger console or a host file
+///
+/// Messages go to the console until [`set_destination`](Self::set_destination)
+/// points the logger elsewhere.
+///
+/// With the `critical-section` feature, the handle is only ever opened once
+/// and each message is written with interrupts disabled, so that messages
+/// from different contexts don't interleave. [`set_try_lock`](Self::set_try_lock)
+/// instead drops messages logged while another is still being written.
 pub struct GlobalLogger {
     handle: AtomicUsize,
+    destination: AtomicUsize,
+    #[cfg(feature = "critical-section")]
+    busy: AtomicBool,
+    #[cfg(feature = "critical-section")]
+    try_lock: AtomicBool,
 }
 
 #[cfg(feature = "const-default")]
@@ -78,23 +281,152 @@ impl GlobalLogger {
     pub const fn new() -> Self {
         Self {
             handle: AtomicUsize::new(0),
+            destination: AtomicUsize::new(0),
+            #[cfg(feature = "critical-section")]
+            busy: AtomicBool::new(false),
+            #[cfg(feature = "critical-section")]
+            try_lock: AtomicBool::new(false),
         }
     }
 
     pub fn handle(&self) -> Option<Handle> {
-        Some(match NonZeroUsize::new(self.handle.load(Ordering::Relaxed)) {
-            None => {
-                let fd = open::<()>(cstr!(":tt"), Mode::MODE_APPEND).ok()?;
-                self.handle.store(fd.get(), Ordering::Relaxed);
-                Handle::from_fd(fd)
+        match NonZeroUsize::new(self.handle.load(Ordering::Acquire)) {
+            Some(fd) => Some(Handle::from_fd(fd)),
+            #[cfg(feature = "critical-section")]
+            None => critical_section::with(|_| match NonZeroUsize::new(self.handle.load(Ordering::Relaxed)) {
+                Some(fd) => Some(Handle::from_fd(fd)),
+                None => self.open_handle(),
+            }),
+            #[cfg(not(feature = "critical-section"))]
+            None => self.open_handle(),
+        }
+    }
+
+    fn open_handle(&self) -> Option<Handle> {
+        let fd = open::<()>(cstr!(":tt"), Mode::MODE_APPEND).ok()?;
+        self.handle.store(fd.get(), Ordering::Release);
+        Some(Handle::from_fd(fd))
+    }
+
+    #[inline]
+    pub fn destination(&self) -> Destination {
+        Destination::decode(self.destination.load(Ordering::Acquire))
+    }
+
+    /// Sends further messages to `destination`, returning the previous one
+    ///
+    /// The logger doesn't take ownership of file handles, so the caller is
+    /// responsible for closing the returned one once it is no longer needed.
+    pub fn set_destination(&self, destination: Destination) -> Destination {
+        let replace = || {
+            let previous = self.destination.load(Ordering::Acquire);
+            self.destination.store(destination.encode(), Ordering::Release);
+            Destination::decode(previous)
+        };
+
+        // messages are written inside a critical section unless try-locking,
+        // so this also waits for any in progress to finish with the old handle
+        #[cfg(feature = "critical-section")]
+        return critical_section::with(|_| replace());
+        #[cfg(not(feature = "critical-section"))]
+        replace()
+    }
+
+    /// Opens `path` for appending and sends further messages to it
+    ///
+    /// With `tee`, messages also still go to the console. The previous
+    /// destination is returned as with [`set_destination`](Self::set_destination).
+    pub fn open_destination<E: Errno>(&self, path: &CStr, tee: bool) -> Result<Destination, E> {
+        let handle = Handle::open(path, Mode::MODE_APPEND)?;
+        Ok(self.set_destination(match tee {
+            true => Destination::Tee(handle),
+            false => Destination::File(handle),
+        }))
+    }
+
+    /// Drop messages instead of waiting for the one currently being written
+    ///
+    /// Messages are then written with interrupts enabled, so this suits
+    /// loggers used from interrupt handlers with tight latency requirements.
+    #[cfg(feature = "critical-section")]
+    pub fn set_try_lock(&self, enabled: bool) {
+        self.try_lock.store(enabled, Ordering::Relaxed);
+    }
+
+    fn output(&self) -> Option<Output> {
+        Some(match self.destination() {
+            Destination::Console => Output {
+                console: Some(self.handle()?),
+                file: None,
+                failed: false,
+            },
+            Destination::File(file) => Output {
+                console: None,
+                file: Some(file),
+                failed: false,
+            },
+            Destination::Tee(file) => Output {
+                console: self.handle(),
+                file: Some(file),
+                failed: false,
             },
-            Some(fd) => Handle::from_fd(fd),
         })
     }
 
+    /// Runs `f` with exclusive access to the output handles, if any could be
+    /// opened, or returns `None` if the lock was busy
+    #[cfg(feature = "critical-section")]
+    fn lock<R, F: FnOnce(Option<Output>) -> R>(&self, f: F) -> Option<R> {
+        if !self.try_lock.load(Ordering::Relaxed) {
+            return Some(critical_section::with(|_| f(self.output())))
+        }
+
+        let acquired = critical_section::with(|_| match self.busy.load(Ordering::Relaxed) {
+            true => false,
+            false => {
+                self.busy.store(true, Ordering::Relaxed);
+                true
+            },
+        });
+        if !acquired {
+            return None
+        }
+        let res = f(self.output());
+        self.busy.store(false, Ordering::Release);
+        Some(res)
+    }
+
+    #[cfg(not(feature = "critical-section"))]
+    #[inline]
+    fn lock<R, F: FnOnce(Option<Output>) -> R>(&self, f: F) -> Option<R> {
+        Some(f(self.output()))
+    }
+
+    #[inline]
+    fn locked<R, F: FnOnce(Output) -> Option<R>>(&self, f: F) -> Option<R> {
+        self.lock(|output| output.and_then(f)).flatten()
+    }
+
     #[inline]
     pub fn log(&self, str: &str) -> Option<()> {
-        self.handle()?.write_all::<()>(str.as_bytes()).ok()
+        self.locked(|mut output| output.write(str.as_bytes()))
+    }
+
+    /// Like [`log`](Self::log), but reports why the message wasn't written
+    pub fn try_log<E: Errno>(&self, str: &str) -> Result<(), LogError<E>> {
+        match self.lock(|output| output.map(|mut output| output.write_all(str.as_bytes()))) {
+            None => Err(LogError::Busy),
+            Some(None) => Err(LogError::NoConsole),
+            Some(Some(res)) => res.map_err(LogError::Write),
+        }
+    }
+
+    /// Writes a whole formatted message while holding the lock
+    pub fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.locked(|mut output| match fmt::write(&mut output, args) {
+            Ok(()) if !output.failed => Some(()),
+            _ => None,
+        }).ok_or(fmt::Error)
     }
 
     pub fn into_handle(self) -> Option<OwnedHandle> {
@@ -103,19 +435,102 @@ impl GlobalLogger {
     }
 }
 
+/// Why [`GlobalLogger::try_log`] didn't write a message
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum LogError<E> {
+    /// Another context was writing and [`set_try_lock`](GlobalLogger::set_try_lock)
+    /// is enabled, so the message was dropped without asking the host
+    Busy,
+    /// The console couldn't be opened
+    NoConsole,
+    Write(WriteAllError<E>),
+}
+
+impl<E: fmt::Display> fmt::Display for LogError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogError::Busy => f.write_str("logger busy"),
+            LogError::NoConsole => f.write_str("console unavailable"),
+            LogError::Write(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "ufmt")]
+impl<E: ufmt::uDisplay> ufmt::uDisplay for LogError<E> {
+    fn fmt<W: ufmt::uWrite + ?Sized>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            LogError::Busy => f.write_str("logger busy"),
+            LogError::NoConsole => f.write_str("console unavailable"),
+            LogError::Write(e) => ufmt::uDisplay::fmt(e, f),
+        }
+    }
+}
+
+/// The handles that the logger has locked for a message
+struct Output {
+    console: Option<Handle>,
+    file: Option<Handle>,
+    /// A fragment of the current message failed to write somewhere
+    failed: bool,
+}
+
+impl Output {
+    fn write_all<E: Errno>(&mut self, bytes: &[u8]) -> Result<(), WriteAllError<E>> {
+        // a failing console shouldn't keep messages out of the file
+        let console = self.console.map_or(Ok(()), |console| console.write_all(bytes));
+        let file = self.file.map_or(Ok(()), |file| file.write_all(bytes));
+        console.and(file)
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) -> Option<()> {
+        self.write_all::<()>(bytes).ok()
+    }
+}
+
+impl fmt::Write for Output {
+    /// Never fails, so that `fmt::write` hands every fragment to both handles
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.write(s.as_bytes()).is_none() {
+            self.failed = true;
+        }
+        Ok(())
+    }
+}
+
 impl<'a> fmt::Write for &'a GlobalLogger {
     #[inline]
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.log(s).ok_or(fmt::Error)
     }
+
+    #[inline]
+    fn write_fmt(&mut self, args: fmt::Arguments) -> fmt::Result {
+        GlobalLogger::write_fmt(*self, args)
+    }
+}
+
+impl Sink for GlobalLogger {
+    #[inline]
+    fn write_str(&self, s: &str) -> fmt::Result {
+        self.log(s).ok_or(fmt::Error)
+    }
+
+    #[inline]
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        GlobalLogger::write_fmt(self, args)
+    }
 }
 
 #[cfg(feature = "ufmt-write")]
 impl<'a> ufmt_write::uWrite for &'a GlobalLogger {
-    type Error = (); // TODO: return syscall error code here instead?
+    type Error = LogError<isize>;
 
     #[inline]
     fn write_str(&mut self, s: &str) -> Result<(), Self::Error> {
-        self.log(s).ok_or(())
+        self.try_log(s)
     }
 }
@@ -0,0 +1,201 @@
+use cstrptr::CStr;
+use crate::io::{Errno, system};
+#[cfg(target_has_atomic = "32")]
+use crate::temp::{TempFile, TempFileError};
+
+/// Where a host command's output stream should go
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum Stdio {
+    /// Shares the debugger's console
+    Inherit,
+    /// Redirected into a [`TempFile`] that can be read back afterwards
+    #[cfg(target_has_atomic = "32")]
+    Capture,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum CommandError<E> {
+    Io(E),
+    /// The command line does not fit in the builder's buffer
+    TooLong,
+    InteriorNul,
+    #[cfg(target_has_atomic = "32")]
+    TempFile(TempFileError<E>),
+}
+
+#[cfg(target_has_atomic = "32")]
+impl<E> From<TempFileError<E>> for CommandError<E> {
+    fn from(e: TempFileError<E>) -> Self {
+        CommandError::TempFile(e)
+    }
+}
+
+/// A host shell command line, run through `SYS_SYSTEM`
+///
+/// Arguments are appended verbatim and separated by spaces. The host shell
+/// is unknown to the target, so any quoting is left to the caller.
+#[derive(Clone, Debug)]
+pub struct Command<const N: usize = 128> {
+    buffer: [u8; N],
+    len: usize,
+    stdout: Stdio,
+    stderr: Stdio,
+}
+
+/// The result of running a [`Command`]
+#[derive(Debug)]
+pub struct Output {
+    status: usize,
+    #[cfg(target_has_atomic = "32")]
+    stdout: Option<TempFile>,
+    #[cfg(target_has_atomic = "32")]
+    stderr: Option<TempFile>,
+}
+
+impl<const N: usize> Command<N> {
+    pub fn new(program: &str) -> Self {
+        let mut command = Self {
+            buffer: [0; N],
+            len: 0,
+            stdout: Stdio::Inherit,
+            stderr: Stdio::Inherit,
+        };
+        command.push(program.as_bytes());
+        command
+    }
+
+    #[inline]
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.push(b" ");
+        self.push(arg.as_bytes());
+        self
+    }
+
+    pub fn args<'a, I: IntoIterator<Item = &'a str>>(&mut self, args: I) -> &mut Self {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    #[inline]
+    pub fn stdout(&mut self, stdio: Stdio) -> &mut Self {
+        self.stdout = stdio;
+        self
+    }
+
+    #[inline]
+    pub fn stderr(&mut self, stdio: Stdio) -> &mut Self {
+        self.stderr = stdio;
+        self
+    }
+
+    /// The command line built so far, without any output redirection
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len.min(N)]
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        // overflow is remembered by letting len run past N
+        if let Some(dest) = self.buffer.get_mut(self.len..self.len + bytes.len()) {
+            dest.copy_from_slice(bytes);
+        }
+        self.len += bytes.len();
+    }
+
+    /// Runs the command and returns its exit status along with any captured output
+    pub fn output<E: Errno>(&self) -> Result<Output, CommandError<E>> {
+        let mut line = self.clone();
+
+        #[cfg(target_has_atomic = "32")]
+        let stdout = match self.stdout {
+            Stdio::Capture => Some(line.redirect(b" >", TempFile::new()?)),
+            Stdio::Inherit => None,
+        };
+        #[cfg(target_has_atomic = "32")]
+        let stderr = match self.stderr {
+            Stdio::Capture => Some(line.redirect(b" 2>", TempFile::new()?)),
+            Stdio::Inherit => None,
+        };
+
+        line.push(b"\0");
+        let cmd = line.buffer.get(..line.len).ok_or(CommandError::TooLong)?;
+        if cmd[..cmd.len() - 1].contains(&0) {
+            return Err(CommandError::InteriorNul);
+        }
+        let cmd = unsafe { CStr::from_bytes_with_nul_unchecked(cmd) };
+
+        Ok(Output {
+            status: system(cmd).map_err(CommandError::Io)?,
+            #[cfg(target_has_atomic = "32")]
+            stdout,
+            #[cfg(target_has_atomic = "32")]
+            stderr,
+        })
+    }
+
+    /// Runs the command and returns its exit status
+    #[inline]
+    pub fn status<E: Errno>(&self) -> Result<usize, CommandError<E>> {
+        self.output().map(|output| output.status)
+    }
+
+    #[cfg(target_has_atomic = "32")]
+    fn redirect(&mut self, op: &[u8], file: TempFile) -> TempFile {
+        self.push(op);
+        self.push(file.path().to_bytes());
+        file
+    }
+}
+
+impl Output {
+    /// The host's `system()` return value
+    #[inline]
+    pub const fn status(&self) -> usize {
+        self.status
+    }
+
+    #[inline]
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+
+    #[cfg(target_has_atomic = "32")]
+    #[inline]
+    pub fn stdout(&self) -> Option<&TempFile> {
+        self.stdout.as_ref()
+    }
+
+    #[cfg(target_has_atomic = "32")]
+    #[inline]
+    pub fn stderr(&self) -> Option<&TempFile> {
+        self.stderr.as_ref()
+    }
+
+    /// Reads captured stdout from the start into `buffer`, returning the length read
+    #[cfg(target_has_atomic = "32")]
+    pub fn read_stdout<E: Errno>(&self, buffer: &mut [u8]) -> Result<usize, E> {
+        match &self.stdout {
+            Some(file) => read_captured(file, buffer),
+            None => Ok(0),
+        }
+    }
+
+    /// Reads captured stderr from the start into `buffer`, returning the length read
+    #[cfg(target_has_atomic = "32")]
+    pub fn read_stderr<E: Errno>(&self, buffer: &mut [u8]) -> Result<usize, E> {
+        match &self.stderr {
+            Some(file) => read_captured(file, buffer),
+            None => Ok(0),
+        }
+    }
+}
+
+#[cfg(target_has_atomic = "32")]
+fn read_captured<E: Errno>(file: &TempFile, buffer: &mut [u8]) -> Result<usize, E> {
+    file.seek_set(0)?;
+    let left = file.read(buffer)?;
+    Ok(buffer.len().saturating_sub(left))
+}
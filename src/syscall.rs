@@ -1,3 +1,5 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
 #[macro_export]
 macro_rules! syscall {
     ($syscall:expr) => {
@@ -13,8 +15,97 @@ pub unsafe fn syscall0<S: Into<usize>>(syscall: S) -> usize {
     self::syscall::<_, usize>(syscall, 0)
 }
 
-#[cfg(any(thumb, arm))]
+/// Whether a debugger is assumed to be servicing calls, see [`set_available`]
+static AVAILABLE: AtomicU8 = AtomicU8::new(AUTO);
+const AUTO: u8 = 0;
+const AVAILABLE_YES: u8 = 1;
+const AVAILABLE_NO: u8 = 2;
+
+/// Overrides whether a debugger is attached to service semihosting calls
+///
+/// While unavailable, calls fail with `usize::MAX` instead of trapping, so
+/// [`io`](crate::io) functions return errors, printing does nothing and
+/// [`exit`](crate::exit) spins in place.
+/// `None` restores detection, which reads `DHCSR.C_DEBUGEN` on ARMv7-M and
+/// ARMv8-M and otherwise assumes a debugger is present.
+///
+/// Software can't read DHCSR on ARMv6-M, so Cortex-M0 and M0+ firmware that
+/// may run without a debugger must call `set_available(Some(false))` itself,
+/// or the first call will HardFault.
+pub fn set_available(available: Option<bool>) {
+    AVAILABLE.store(encode_available(available), Ordering::Relaxed);
+}
+
+#[inline]
+const fn encode_available(available: Option<bool>) -> u8 {
+    match available {
+        None => AUTO,
+        Some(true) => AVAILABLE_YES,
+        Some(false) => AVAILABLE_NO,
+    }
+}
+
+#[inline]
+fn decode_available<F: FnOnce() -> bool>(state: u8, detect: F) -> bool {
+    match state {
+        AUTO => detect(),
+        state => state == AVAILABLE_YES,
+    }
+}
+
+/// Whether semihosting calls will be made, see [`set_available`]
+///
+/// Without a debugger, the trap would otherwise escalate to a HardFault on
+/// Cortex-M. Note that some probes leave `C_DEBUGEN` set after detaching, and
+/// that this is always true on ARMv6-M unless overridden.
+#[inline]
+pub fn is_available() -> bool {
+    decode_available(AVAILABLE.load(Ordering::Relaxed), debugger_attached)
+}
+
+// ARMv6-M doesn't let software read DHCSR
+#[cfg(all(cortex_m, not(target = "thumbv6m-none-eabi"), not(feature = "mock")))]
+#[inline]
+fn debugger_attached() -> bool {
+    const DHCSR: *const u32 = 0xe000_edf0 as *const u32;
+    const C_DEBUGEN: u32 = 1 << 0;
+
+    unsafe { core::ptr::read_volatile(DHCSR) & C_DEBUGEN != 0 }
+}
+
+#[cfg(not(all(cortex_m, not(target = "thumbv6m-none-eabi"), not(feature = "mock"))))]
+#[inline]
+fn debugger_attached() -> bool {
+    true
+}
+
 pub unsafe fn syscall<S: Into<usize>, T: Into<usize>>(syscall: S, message: T) -> usize {
+    if !is_available() {
+        return core::usize::MAX
+    }
+    #[cfg(feature = "syscall-log")]
+    return crate::syscall_log::logged(syscall.into(), message.into(), syscall_raw);
+    #[cfg(not(feature = "syscall-log"))]
+    syscall_raw(syscall.into(), message.into())
+}
+
+/// Number of words in an operation's parameter block, or 0 if the parameter isn't one
+#[cfg(any(feature = "mock", feature = "syscall-log"))]
+pub(crate) fn block_len(syscall: crate::Syscall) -> usize {
+    use crate::Syscall;
+
+    match syscall {
+        Syscall::Close | Syscall::IsError | Syscall::IsTTY | Syscall::FLen => 1,
+        Syscall::Seek | Syscall::Remove | Syscall::System | Syscall::GetCmdline
+            | Syscall::ReportExceptionExtended => 2,
+        Syscall::Open | Syscall::Write | Syscall::Read | Syscall::TmpNam => 3,
+        Syscall::Rename => 4,
+        _ => 0,
+    }
+}
+
+#[cfg(all(any(thumb, arm), not(feature = "mock")))]
+unsafe fn syscall_raw(syscall: usize, message: usize) -> usize {
     // note on clobbers:
     // - memory is complicated depending on the operation? though indirect pointers mean this still may not be enough hence "volatile"
     // - lr clobbered if in supervisor mode? newlib says so... see "page 13-77 of ARM DUI 0040D"
@@ -57,10 +148,24 @@ pub unsafe fn syscall<S: Into<usize>, T: Into<usize>>(syscall: S, message: T) ->
         out
     }
 
-    syscall_impl(syscall.into(), message.into())
+    syscall_impl(syscall, message)
 }
 
-#[cfg(not(any(thumb, arm)))]
-pub unsafe fn syscall<S: Into<usize>, T: Into<usize>>(_syscall: S, _message: T) -> usize {
+#[cfg(feature = "mock")]
+unsafe fn syscall_raw(syscall: usize, message: usize) -> usize {
+    crate::mock::syscall(syscall, message)
+}
+
+#[cfg(not(any(thumb, arm, feature = "mock")))]
+unsafe fn syscall_raw(_syscall: usize, _message: usize) -> usize {
     unimplemented!("stub")
 }
+
+#[test]
+fn availability() {
+    // AVAILABLE is left alone, as other tests may be making calls
+    assert!(!decode_available(encode_available(Some(false)), || true));
+    assert!(decode_available(encode_available(Some(true)), || false));
+    assert!(decode_available(encode_available(None), || true));
+    assert!(!decode_available(encode_available(None), || false));
+}
@@ -0,0 +1,255 @@
+//! A RAM log of recent semihosting calls, for finding the one that hangs
+//!
+//! Every call made while the `syscall-log` feature is enabled is recorded
+//! before it traps, with its elapsed time filled in once the host returns.
+//! The log is dumped to a host file on [`exit`](crate::exit), and can be read
+//! from a debugger while the target is halted:
+//!
+//! ```text
+//! (gdb) set $log = SEMIHOSTING_SYSCALL_LOG
+//! (gdb) print $log.entries.value[($log.recorded.v.value - 1) % 64]
+//! ```
+//!
+//! An entry whose `elapsed` is `PENDING` is still waiting on the host.
+
+use core::cell::UnsafeCell;
+use core::convert::TryFrom;
+use core::fmt;
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use cstrptr::CStr;
+use crate::busy::{release, try_acquire};
+use crate::io::{Errno, OwnedHandle, WriteAllError};
+use crate::{Mode, Syscall};
+
+/// Number of calls kept in RAM
+pub const LOG_LEN: usize = 64;
+
+/// `elapsed` of a call the host hasn't returned from
+pub const PENDING: u32 = u32::MAX;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[repr(C)]
+pub struct Entry {
+    pub operation: usize,
+    /// The parameter block, or the parameter itself where it isn't a pointer
+    pub args: [usize; 4],
+    pub result: usize,
+    /// Clock ticks at the start of the call
+    pub start: u32,
+    pub elapsed: u32,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (syscall, len) = match Syscall::try_from(self.operation) {
+            Ok(syscall) => (Some(syscall), crate::syscall::block_len(syscall).max(1)),
+            Err(()) => (None, 1),
+        };
+        match syscall {
+            Some(syscall) => write!(f, "{:?}(", syscall)?,
+            None => write!(f, "{:#x}(", self.operation)?,
+        }
+        for (i, arg) in self.args[..len].iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{:#x}", arg)?;
+        }
+        match self.elapsed {
+            PENDING => write!(f, ") pending since {}", self.start),
+            elapsed => write!(f, ") = {:#x} at {} took {}", self.result, self.start, elapsed),
+        }
+    }
+}
+
+const EMPTY: Entry = Entry {
+    operation: 0,
+    args: [0; 4],
+    result: 0,
+    start: 0,
+    elapsed: 0,
+};
+
+#[repr(C)]
+pub struct Log {
+    /// Total calls seen, the latest is at this minus one modulo `LOG_LEN`
+    recorded: AtomicUsize,
+    entries: UnsafeCell<[Entry; LOG_LEN]>,
+}
+
+// Only written while holding BUSY, which keeps interrupts on the same core
+// out. Nothing stops another core, so the log must only be used from one.
+unsafe impl Sync for Log {}
+
+#[no_mangle]
+pub static SEMIHOSTING_SYSCALL_LOG: Log = Log {
+    recorded: AtomicUsize::new(0),
+    entries: UnsafeCell::new([EMPTY; LOG_LEN]),
+};
+
+static CLOCK: AtomicUsize = AtomicUsize::new(0);
+static PATH_PTR: AtomicUsize = AtomicUsize::new(0);
+static PATH_LEN: AtomicUsize = AtomicUsize::new(0);
+/// Set while recording or dumping, so that calls made by the clock or the dump aren't logged
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Sets the timestamp source, without which times are all zero
+///
+/// The clock may itself use semihosting, such as [`io::clock`](crate::io::clock).
+pub fn set_clock(clock: fn() -> u32) {
+    CLOCK.store(clock as usize, Ordering::Relaxed);
+}
+
+fn timestamp() -> u32 {
+    match CLOCK.load(Ordering::Relaxed) {
+        0 => 0,
+        clock => {
+            let clock: fn() -> u32 = unsafe { core::mem::transmute(clock) };
+            clock()
+        },
+    }
+}
+
+/// Sets the host path that [`exit`](crate::exit) writes the log to
+///
+/// Defaults to `syscalls.log`.
+pub fn set_path(path: &'static CStr) {
+    let path = path.to_bytes();
+    PATH_LEN.store(0, Ordering::Relaxed);
+    PATH_PTR.store(path.as_ptr() as usize, Ordering::Relaxed);
+    PATH_LEN.store(path.len() + 1, Ordering::Relaxed);
+}
+
+fn path() -> &'static CStr {
+    match PATH_LEN.load(Ordering::Relaxed) {
+        0 => cstrptr::cstr!("syscalls.log"),
+        len => unsafe {
+            CStr::from_bytes_with_nul_unchecked(slice::from_raw_parts(PATH_PTR.load(Ordering::Relaxed) as *const u8, len))
+        },
+    }
+}
+
+/// Calls the latest `LOG_LEN` entries with `f`, oldest first
+pub fn for_each<F: FnMut(&Entry)>(mut f: F) {
+    let recorded = SEMIHOSTING_SYSCALL_LOG.recorded.load(Ordering::Acquire);
+    let entries = unsafe { &*SEMIHOSTING_SYSCALL_LOG.entries.get() };
+    for index in recorded.saturating_sub(LOG_LEN)..recorded {
+        f(&entries[index % LOG_LEN]);
+    }
+}
+
+struct Line {
+    buffer: [u8; 160],
+    len: usize,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; 160],
+            len: 0,
+        }
+    }
+}
+
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        match self.buffer.get_mut(self.len..end) {
+            Some(buffer) => buffer.copy_from_slice(s.as_bytes()),
+            None => return Err(fmt::Error),
+        }
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Writes the log to a host file, one call per line
+pub fn dump<E: Errno>(path: &CStr) -> Result<(), WriteAllError<E>> {
+    if !try_acquire(&BUSY) {
+        return Ok(())
+    }
+    let res = dump_locked(path);
+    release(&BUSY);
+    res
+}
+
+fn dump_locked<E: Errno>(path: &CStr) -> Result<(), WriteAllError<E>> {
+    let file = OwnedHandle::open(path, Mode::MODE_READ_WRITE).map_err(WriteAllError::Io)?;
+    let mut res = Ok(());
+    for_each(|entry| {
+        if res.is_err() {
+            return
+        }
+        let mut line = Line::new();
+        // a line too long for the buffer is cut short rather than dropped
+        let _ = fmt::write(&mut line, format_args!("{}", entry));
+        let len = line.len.min(line.buffer.len() - 1);
+        line.buffer[len] = b'\n';
+        res = file.write_all(&line.buffer[..=len]);
+    });
+    res
+}
+
+pub(crate) fn dump_on_exit() {
+    let _ = dump::<()>(path());
+}
+
+pub(crate) unsafe fn logged(operation: usize, parameter: usize, syscall: unsafe fn(usize, usize) -> usize) -> usize {
+    if !try_acquire(&BUSY) {
+        return syscall(operation, parameter)
+    }
+    let mut args = [parameter, 0, 0, 0];
+    if let Ok(len) = Syscall::try_from(operation).map(crate::syscall::block_len) {
+        if len > 0 {
+            args[..len].copy_from_slice(slice::from_raw_parts(parameter as *const usize, len));
+        }
+    }
+    let index = SEMIHOSTING_SYSCALL_LOG.recorded.load(Ordering::Relaxed);
+    let entry: *mut Entry = &mut (*SEMIHOSTING_SYSCALL_LOG.entries.get())[index % LOG_LEN];
+    let start = timestamp();
+    *entry = Entry {
+        operation,
+        args,
+        result: 0,
+        start,
+        elapsed: PENDING,
+    };
+    SEMIHOSTING_SYSCALL_LOG.recorded.store(index + 1, Ordering::Release);
+    release(&BUSY);
+
+    let result = syscall(operation, parameter);
+
+    if try_acquire(&BUSY) {
+        // an interrupt may have lapped the log while the host was busy
+        if SEMIHOSTING_SYSCALL_LOG.recorded.load(Ordering::Relaxed) - index <= LOG_LEN {
+            (*entry).result = result;
+            (*entry).elapsed = timestamp().wrapping_sub(start).min(PENDING - 1);
+        }
+        release(&BUSY);
+    }
+    result
+}
+
+#[test]
+fn entry_display() {
+    let check = |entry: &Entry, expected: &[u8]| {
+        let mut line = Line::new();
+        fmt::write(&mut line, format_args!("{}", entry)).unwrap();
+        assert_eq!(&line.buffer[..line.len], expected);
+    };
+    let mut entry = Entry {
+        operation: Syscall::Write as usize,
+        args: [1, 0x2000_0100, 16, 0],
+        result: 0,
+        start: 120,
+        elapsed: 35,
+    };
+    check(&entry, b"Write(0x1, 0x20000100, 0x10) = 0x0 at 120 took 35");
+    entry.elapsed = PENDING;
+    check(&entry, b"Write(0x1, 0x20000100, 0x10) pending since 120");
+    entry.operation = 0x100;
+    check(&entry, b"0x100(0x1) pending since 120");
+}
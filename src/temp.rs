@@ -0,0 +1,138 @@
+use core::ops::Deref;
+#[cfg(target_has_atomic = "32")]
+use core::sync::atomic::{AtomicU32, Ordering};
+use cstrptr::CStr;
+use crate::io::{Handle, Errno, tmpnam_cstr, remove};
+use crate::Mode;
+
+/// Space reserved for host temporary names, which must fit `L_tmpnam` on the host
+pub const NAME_LEN: usize = 64;
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum TempFileError<E> {
+    Io(E),
+    /// All 256 temporary file ids are in use
+    Exhausted,
+}
+
+/// A read/write temporary file on the host, removed when dropped
+///
+/// The host names temporary files after an 8-bit id, so at most 256 of them
+/// can exist at once.
+#[derive(Debug)]
+pub struct TempFile {
+    handle: Handle,
+    id: u8,
+    #[cfg(target_has_atomic = "32")]
+    allocated: bool,
+    name: [u8; NAME_LEN],
+}
+
+impl TempFile {
+    /// Creates a temporary file with an id that no other `TempFile::new` is using
+    #[cfg(target_has_atomic = "32")]
+    pub fn new<E: Errno>() -> Result<Self, TempFileError<E>> {
+        let id = alloc_id().ok_or(TempFileError::Exhausted)?;
+        let mut file = Self::create(id).map_err(|e| {
+            free_id(id);
+            TempFileError::Io(e)
+        })?;
+        file.allocated = true;
+        Ok(file)
+    }
+
+    /// Creates a temporary file with a caller-chosen id
+    ///
+    /// Ids passed here are not reserved, so they may collide with ids handed
+    /// out by [`TempFile::new`].
+    #[inline]
+    pub fn with_id<E: Errno>(id: u8) -> Result<Self, E> {
+        Self::create(id)
+    }
+
+    fn create<E: Errno>(id: u8) -> Result<Self, E> {
+        let mut name = [0u8; NAME_LEN];
+        let path = tmpnam_cstr(id, &mut name).map_err(|()| E::last_error())?;
+        let handle = Handle::open(path, Mode::MODE_READ_WRITE | Mode::MODE_UPDATE | Mode::BINARY)?;
+        Ok(Self {
+            handle,
+            id,
+            #[cfg(target_has_atomic = "32")]
+            allocated: false,
+            name,
+        })
+    }
+
+    #[inline]
+    pub const fn id(&self) -> u8 {
+        self.id
+    }
+
+    #[inline]
+    pub const fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// The host path of the file
+    pub fn path(&self) -> &CStr {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(NAME_LEN - 1);
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.name[..=len]) }
+    }
+}
+
+impl Deref for TempFile {
+    type Target = Handle;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl AsRef<Handle> for TempFile {
+    #[inline]
+    fn as_ref(&self) -> &Handle {
+        &self.handle
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // the file has to be closed before the host will let us remove it
+        let _ = self.handle.close::<()>();
+        let _ = remove::<()>(self.path());
+
+        #[cfg(target_has_atomic = "32")]
+        {
+            if self.allocated {
+                free_id(self.id);
+            }
+        }
+    }
+}
+
+#[cfg(target_has_atomic = "32")]
+static IDS: [AtomicU32; 8] = {
+    const FREE: AtomicU32 = AtomicU32::new(0);
+    [FREE; 8]
+};
+
+#[cfg(target_has_atomic = "32")]
+fn alloc_id() -> Option<u8> {
+    for (word, ids) in IDS.iter().enumerate() {
+        let mut used = ids.load(Ordering::Relaxed);
+        while used != u32::MAX {
+            let bit = (!used).trailing_zeros();
+            match ids.compare_exchange_weak(used, used | (1 << bit), Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return Some((word * 32) as u8 + bit as u8),
+                Err(current) => used = current,
+            }
+        }
+    }
+    None
+}
+
+#[cfg(target_has_atomic = "32")]
+fn free_id(id: u8) {
+    IDS[id as usize / 32].fetch_and(!(1 << (id % 32)), Ordering::Release);
+}
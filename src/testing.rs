@@ -0,0 +1,206 @@
+use core::any::type_name;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::{print, println};
+
+mod report;
+
+pub use self::report::{ReportFormat, report_to};
+
+/// A test that can be passed to [`runner`]
+///
+/// Implemented for plain functions, so `#[test_case]` items work directly.
+pub trait Testable {
+    fn name(&self) -> &str;
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn name(&self) -> &str {
+        let name = type_name::<T>();
+        // libtest names don't include the crate
+        match name.split_once("::") {
+            Some((_, name)) => name,
+            None => name,
+        }
+    }
+
+    #[inline]
+    fn run(&self) {
+        self()
+    }
+}
+
+/// A named test, for use without `custom_test_frameworks`
+///
+/// See [`test_main!`](crate::test_main).
+#[derive(Copy, Clone, Debug)]
+pub struct TestCase {
+    name: &'static str,
+    test: fn(),
+}
+
+impl TestCase {
+    #[inline]
+    pub const fn new(name: &'static str, test: fn()) -> Self {
+        Self {
+            name,
+            test,
+        }
+    }
+}
+
+impl Testable for TestCase {
+    #[inline]
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    #[inline]
+    fn run(&self) {
+        (self.test)()
+    }
+}
+
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+static FILTERED: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_NAME: AtomicUsize = AtomicUsize::new(0);
+static CURRENT_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs tests with libtest-style output, then exits
+///
+/// Arguments from the semihosting command line are used as name filters,
+/// with `--exact` requiring full matches. Failing tests panic, so the
+/// `#[panic_handler]` must call [`panic`] to report them. Results are also
+/// written to the file set up by [`report_to`], if any.
+///
+/// ```ignore
+/// #![feature(custom_test_frameworks)]
+/// #![test_runner(semihosting::testing::runner)]
+/// ```
+pub fn runner(tests: &[&dyn Testable]) -> ! {
+    if crate::parse_cmdline(|cmdline| run(tests, cmdline.to_bytes())).is_err() {
+        run(tests, b"")
+    }
+    crate::exit()
+}
+
+fn run(tests: &[&dyn Testable], cmdline: &[u8]) {
+    let filters = Filters::new(cmdline);
+    let total = tests.iter().filter(|test| filters.matches(test.name())).count();
+    PASSED.store(0, Ordering::Relaxed);
+    FILTERED.store(tests.len() - total, Ordering::Relaxed);
+
+    println!();
+    println!("running {} test{}", total, if total == 1 { "" } else { "s" });
+    report::start(total);
+    for test in tests.iter().filter(|test| filters.matches(test.name())) {
+        let name = test.name();
+        print!("test {} ... ", name);
+        CURRENT_NAME.store(name.as_ptr() as usize, Ordering::Relaxed);
+        CURRENT_LEN.store(name.len(), Ordering::Relaxed);
+        report::test_started();
+        test.run();
+        CURRENT_LEN.store(0, Ordering::Relaxed);
+        println!("ok");
+        let passed = PASSED.load(Ordering::Relaxed) + 1;
+        PASSED.store(passed, Ordering::Relaxed);
+        report::passed(passed, name);
+    }
+    report::finish();
+    println!();
+    println!("test result: ok. {} passed; 0 failed; 0 ignored; 0 measured; {} filtered out",
+        total, tests.len() - total
+    );
+    println!();
+}
+
+fn current_test() -> Option<&'static str> {
+    match CURRENT_LEN.load(Ordering::Relaxed) {
+        0 => None,
+        len => unsafe {
+            let name = core::slice::from_raw_parts(CURRENT_NAME.load(Ordering::Relaxed) as *const u8, len);
+            Some(core::str::from_utf8_unchecked(name))
+        },
+    }
+}
+
+/// Reports the running test as failed and exits
+///
+/// ```ignore
+/// #[panic_handler]
+/// fn panic(info: &core::panic::PanicInfo) -> ! {
+///     semihosting::testing::panic(info)
+/// }
+/// ```
+pub fn panic(info: &PanicInfo) -> ! {
+    match current_test() {
+        Some(name) => {
+            let passed = PASSED.load(Ordering::Relaxed);
+            report::failed(passed + 1, name, info);
+            report::finish();
+
+            println!("FAILED");
+            println!();
+            println!("failures:");
+            println!();
+            println!("---- {} ----", name);
+            println!("{}", info);
+            println!();
+            println!("test result: FAILED. {} passed; 1 failed; 0 ignored; 0 measured; {} filtered out",
+                passed, FILTERED.load(Ordering::Relaxed)
+            );
+            println!();
+        },
+        None => println!("{}", info),
+    }
+    crate::abort()
+}
+
+struct Filters<'a> {
+    cmdline: &'a [u8],
+    exact: bool,
+}
+
+impl<'a> Filters<'a> {
+    fn new(cmdline: &'a [u8]) -> Self {
+        let mut filters = Self {
+            cmdline,
+            exact: false,
+        };
+        filters.exact = filters.args().any(|arg| arg == b"--exact");
+        filters
+    }
+
+    fn args(&self) -> impl Iterator<Item = &'a [u8]> {
+        // the first argument is the program itself
+        self.cmdline.split(|c| c.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .skip(1)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.as_bytes();
+        let exact = self.exact;
+        let mut filters = self.args().filter(|arg| !arg.starts_with(b"-")).peekable();
+        filters.peek().is_none() || filters.any(|filter| match exact {
+            true => name == filter,
+            false => name.windows(filter.len()).any(|window| window == filter),
+        })
+    }
+}
+
+#[test]
+fn test_filters() {
+    let filters = Filters::new(b"firmware.elf io:: path");
+    assert!(filters.matches("io::seek"));
+    assert!(filters.matches("path::push"));
+    assert!(!filters.matches("print::print"));
+
+    let filters = Filters::new(b"firmware.elf --exact path::push");
+    assert!(filters.matches("path::push"));
+    assert!(!filters.matches("path::push_fmt"));
+
+    assert!(Filters::new(b"firmware.elf").matches("anything"));
+    assert!(Filters::new(b"").matches("anything"));
+}
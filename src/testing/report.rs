@@ -0,0 +1,166 @@
+use core::fmt::{self, Write};
+use core::num::NonZeroUsize;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cstrptr::CStr;
+use crate::io::{self, Errno, Handle, WriteAllError};
+use crate::Mode;
+
+/// Machine-readable test result formats
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub enum ReportFormat {
+    /// Test Anything Protocol, version 13
+    Tap,
+    /// JUnit XML, as consumed by most CI systems
+    JUnit,
+}
+
+static REPORT_FD: AtomicUsize = AtomicUsize::new(0);
+static REPORT_FORMAT: AtomicUsize = AtomicUsize::new(ReportFormat::Tap as usize);
+static TEST_START: AtomicUsize = AtomicUsize::new(0);
+
+/// Streams test results to a host file as each test finishes
+///
+/// Must be called before [`runner`](super::runner). The file is truncated,
+/// and is left well-formed even if a test panics.
+pub fn report_to<E: Errno>(path: &CStr, format: ReportFormat) -> Result<(), E> {
+    let fd = io::open(path, Mode::MODE_READ_WRITE)?;
+    if let Some(old) = NonZeroUsize::new(REPORT_FD.load(Ordering::Relaxed)) {
+        let _ = io::close::<()>(old.get());
+    }
+    REPORT_FORMAT.store(format as usize, Ordering::Relaxed);
+    REPORT_FD.store(fd.get(), Ordering::Relaxed);
+    Ok(())
+}
+
+fn report() -> Option<(Handle, ReportFormat)> {
+    let fd = NonZeroUsize::new(REPORT_FD.load(Ordering::Relaxed))?;
+    let format = match REPORT_FORMAT.load(Ordering::Relaxed) {
+        f if f == ReportFormat::JUnit as usize => ReportFormat::JUnit,
+        _ => ReportFormat::Tap,
+    };
+    Some((Handle::from_fd(fd), format))
+}
+
+/// Escapes text for the quoted strings of the report format
+struct Escape<'a> {
+    report: &'a mut Handle,
+    format: ReportFormat,
+}
+
+impl<'a> Write for Escape<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            let escaped = match (self.format, c) {
+                (ReportFormat::JUnit, '&') => "&amp;",
+                (ReportFormat::JUnit, '<') => "&lt;",
+                (ReportFormat::JUnit, '>') => "&gt;",
+                (ReportFormat::JUnit, '"') => "&quot;",
+                (ReportFormat::JUnit, '\'') => "&apos;",
+                (ReportFormat::Tap, '"') => "\\\"",
+                (ReportFormat::Tap, '\\') => "\\\\",
+                (ReportFormat::Tap, '\n') => "\\n",
+                _ => continue,
+            };
+            self.report.write_str(&s[start..i])?;
+            self.report.write_str(escaped)?;
+            start = i + c.len_utf8();
+        }
+        self.report.write_str(&s[start..])
+    }
+}
+
+fn escape<'a>(report: &'a mut Handle, format: ReportFormat) -> Escape<'a> {
+    Escape {
+        report,
+        format,
+    }
+}
+
+/// Centiseconds since the current test started
+fn elapsed() -> usize {
+    io::clock().unwrap_or(0).saturating_sub(TEST_START.load(Ordering::Relaxed))
+}
+
+/// Splits `module::test` into JUnit's classname and name
+fn junit_names(name: &str) -> (&str, &str) {
+    match name.rfind("::") {
+        Some(sep) => (&name[..sep], &name[sep + 2..]),
+        None => ("", name),
+    }
+}
+
+fn write_case(report: &mut Handle, format: ReportFormat, index: usize, name: &str) -> fmt::Result {
+    let centis = elapsed();
+    match format {
+        ReportFormat::Tap => Write::write_fmt(report, format_args!("ok {} - {} # time={}ms\n", index, name, centis * 10)),
+        ReportFormat::JUnit => {
+            let (class, name) = junit_names(name);
+            report.write_str("<testcase classname=\"")?;
+            escape(report, format).write_str(class)?;
+            report.write_str("\" name=\"")?;
+            escape(report, format).write_str(name)?;
+            Write::write_fmt(report, format_args!("\" time=\"{}.{:02}\"", centis / 100, centis % 100))
+        },
+    }
+}
+
+pub(super) fn start(total: usize) {
+    if let Some((report, format)) = report() {
+        let _: Result<(), WriteAllError<()>> = match format {
+            ReportFormat::Tap => write!(report, "TAP version 13\n1..{}\n", total),
+            ReportFormat::JUnit => write!(report,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n<testsuite name=\"semihosting\" tests=\"{}\">\n",
+                total
+            ),
+        };
+    }
+}
+
+pub(super) fn test_started() {
+    if REPORT_FD.load(Ordering::Relaxed) != 0 {
+        TEST_START.store(io::clock().unwrap_or(0), Ordering::Relaxed);
+    }
+}
+
+pub(super) fn passed(index: usize, name: &str) {
+    if let Some((mut report, format)) = report() {
+        let _ = write_case(&mut report, format, index, name).and_then(|()| match format {
+            ReportFormat::Tap => Ok(()),
+            ReportFormat::JUnit => report.write_str("/>\n"),
+        });
+    }
+}
+
+pub(super) fn failed(index: usize, name: &str, info: &PanicInfo) {
+    if let Some((mut report, format)) = report() {
+        let _ = (|| match format {
+            ReportFormat::Tap => {
+                report.write_str("not ")?;
+                write_case(&mut report, format, index, name)?;
+                report.write_str("  ---\n  message: \"")?;
+                write!(escape(&mut report, format), "{}", info)?;
+                report.write_str("\"\n  ...\nBail out! test panicked\n")
+            },
+            ReportFormat::JUnit => {
+                write_case(&mut report, format, index, name)?;
+                report.write_str("><failure message=\"")?;
+                write!(escape(&mut report, format), "{}", info)?;
+                report.write_str("\"/></testcase>\n")
+            },
+        })();
+    }
+}
+
+pub(super) fn finish() {
+    if let Some((mut report, format)) = report() {
+        let _ = match format {
+            ReportFormat::Tap => Ok(()),
+            ReportFormat::JUnit => report.write_str("</testsuite>\n</testsuites>\n"),
+        };
+        REPORT_FD.store(0, Ordering::Relaxed);
+        let _ = report.close::<()>();
+    }
+}
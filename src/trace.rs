@@ -0,0 +1,266 @@
+use core::cell::UnsafeCell;
+use core::num::NonZeroUsize;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use cstrptr::CStr;
+use crate::busy::{release, try_acquire};
+use crate::io::{Errno, Handle, WriteAllError};
+use crate::Mode;
+
+/// Trace files start with this, followed by [`VERSION`] and three reserved bytes
+pub const MAGIC: [u8; 4] = *b"SHTR";
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 8;
+
+/// Number of records held in RAM between flushes
+pub const BUFFER_LEN: usize = 256;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+#[repr(u8)]
+pub enum Kind {
+    /// Function entry recorded by [`trace!`](crate::trace!)
+    Enter = 1,
+    /// Function exit recorded by [`trace!`](crate::trace!)
+    Exit = 2,
+    /// Function entry recorded by `-Z instrument-mcount`, which has no matching exit
+    Mcount = 3,
+}
+
+/// A trace event, stored as 16 little-endian bytes
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "ufmt", derive(ufmt::derive::uDebug))]
+pub struct Record {
+    pub kind: Kind,
+    pub timestamp: u32,
+    /// Address of (or within) the traced function
+    pub function: u32,
+    /// Return address into the caller, if known
+    pub call_site: u32,
+}
+
+impl Record {
+    pub const LEN: usize = 16;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0] = self.kind as u8;
+        bytes[4..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.function.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.call_site.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::LEN]) -> Option<Self> {
+        let word = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Some(Self {
+            kind: match bytes[0] {
+                1 => Kind::Enter,
+                2 => Kind::Exit,
+                3 => Kind::Mcount,
+                _ => return None,
+            },
+            timestamp: word(4),
+            function: word(8),
+            call_site: word(12),
+        })
+    }
+}
+
+#[test]
+fn record_roundtrip() {
+    let record = Record {
+        kind: Kind::Exit,
+        timestamp: 0x1234_5678,
+        function: 0x0800_0401,
+        call_site: 0x0800_1235,
+    };
+    assert_eq!(Record::decode(&record.encode()), Some(record));
+    assert_eq!(Record::decode(&[0; Record::LEN]), None);
+}
+
+struct Buffer {
+    records: UnsafeCell<[Record; BUFFER_LEN]>,
+}
+
+// only accessed while holding BUSY
+unsafe impl Sync for Buffer {}
+
+const EMPTY: Record = Record {
+    kind: Kind::Enter,
+    timestamp: 0,
+    function: 0,
+    call_site: 0,
+};
+
+static BUFFER: Buffer = Buffer {
+    records: UnsafeCell::new([EMPTY; BUFFER_LEN]),
+};
+/// Total records seen, the write position is this modulo `BUFFER_LEN`
+static RECORDED: AtomicUsize = AtomicUsize::new(0);
+static FLUSHED: AtomicUsize = AtomicUsize::new(0);
+static OUTPUT: AtomicUsize = AtomicUsize::new(0);
+static CLOCK: AtomicUsize = AtomicUsize::new(0);
+/// Set while recording or flushing, so that tracing the tracer is ignored
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Sets the timestamp source, which defaults to a sequence number
+pub fn set_clock(clock: fn() -> u32) {
+    CLOCK.store(clock as usize, Ordering::Relaxed);
+}
+
+fn timestamp() -> u32 {
+    match CLOCK.load(Ordering::Relaxed) {
+        0 => RECORDED.load(Ordering::Relaxed) as u32,
+        clock => {
+            let clock: fn() -> u32 = unsafe { core::mem::transmute(clock) };
+            clock()
+        },
+    }
+}
+
+/// Starts writing records to a host file
+///
+/// Records are flushed whenever the RAM buffer fills up, or by calling
+/// [`flush`]. Without an output file, the buffer keeps the latest
+/// `BUFFER_LEN` records.
+pub fn start<E: Errno>(path: &CStr) -> Result<(), WriteAllError<E>> {
+    stop::<()>().ok();
+    let handle = Handle::open(path, Mode::MODE_READ_WRITE | Mode::BINARY).map_err(WriteAllError::Io)?;
+    let mut header = [0u8; HEADER_LEN];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = VERSION;
+    handle.write_all(&header)?;
+    OUTPUT.store(handle.fd().get(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Flushes any remaining records and closes the output file
+pub fn stop<E: Errno>() -> Result<(), WriteAllError<E>> {
+    let res = flush();
+    if let Some(fd) = NonZeroUsize::new(OUTPUT.load(Ordering::Relaxed)) {
+        OUTPUT.store(0, Ordering::Relaxed);
+        Handle::from_fd(fd).close().map_err(WriteAllError::Io)?;
+    }
+    res
+}
+
+/// Writes buffered records to the output file
+pub fn flush<E: Errno>() -> Result<(), WriteAllError<E>> {
+    if !try_acquire(&BUSY) {
+        return Ok(())
+    }
+    let res = unsafe { flush_locked() };
+    release(&BUSY);
+    res
+}
+
+unsafe fn flush_locked<E: Errno>() -> Result<(), WriteAllError<E>> {
+    let handle = match NonZeroUsize::new(OUTPUT.load(Ordering::Relaxed)) {
+        Some(fd) => Handle::from_fd(fd),
+        None => return Ok(()),
+    };
+    let recorded = RECORDED.load(Ordering::Relaxed);
+    // anything older than a full buffer has already been overwritten
+    let mut next = FLUSHED.load(Ordering::Relaxed).max(recorded.saturating_sub(BUFFER_LEN));
+    let records = &*BUFFER.records.get();
+
+    let mut chunk = [0u8; Record::LEN * 16];
+    while next < recorded {
+        let count = (recorded - next).min(16);
+        for (i, bytes) in chunk.chunks_exact_mut(Record::LEN).take(count).enumerate() {
+            bytes.copy_from_slice(&records[(next + i) % BUFFER_LEN].encode());
+        }
+        handle.write_all(&chunk[..count * Record::LEN])?;
+        next += count;
+        FLUSHED.store(next, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Adds a record to the buffer, flushing it first if it is full
+pub fn record(kind: Kind, function: usize, call_site: usize) {
+    if !try_acquire(&BUSY) {
+        return
+    }
+    unsafe { record_locked(kind, function, call_site) };
+    release(&BUSY);
+}
+
+unsafe fn record_locked(kind: Kind, function: usize, call_site: usize) {
+    let recorded = RECORDED.load(Ordering::Relaxed);
+    if recorded - FLUSHED.load(Ordering::Relaxed) >= BUFFER_LEN {
+        let _ = flush_locked::<()>();
+    }
+    (*BUFFER.records.get())[recorded % BUFFER_LEN] = Record {
+        kind,
+        timestamp: timestamp(),
+        function: function as u32,
+        call_site: call_site as u32,
+    };
+    RECORDED.store(recorded + 1, Ordering::Relaxed);
+}
+
+/// Records the exit of a function traced with [`trace!`](crate::trace!) when dropped
+#[derive(Debug)]
+pub struct Guard {
+    function: usize,
+}
+
+impl Guard {
+    #[inline]
+    pub fn enter(function: usize) -> Self {
+        record(Kind::Enter, function, 0);
+        Self {
+            function,
+        }
+    }
+}
+
+impl Drop for Guard {
+    #[inline]
+    fn drop(&mut self) {
+        record(Kind::Exit, self.function, 0);
+    }
+}
+
+#[cfg(all(feature = "trace-mcount", any(arm, thumb), not(any(target = "thumbv6m-none-eabi", target = "thumbv8m.base-none-eabi"))))]
+extern "C" fn mcount_record(function: usize, call_site: usize) {
+    unsafe { record_locked(Kind::Mcount, function, call_site) }
+}
+
+#[cfg(all(feature = "trace-mcount", thumb, not(any(target = "thumbv6m-none-eabi", target = "thumbv8m.base-none-eabi"))))]
+macro_rules! mcount_mode { () => { ".thumb\n.thumb_func" } }
+#[cfg(all(feature = "trace-mcount", arm))]
+macro_rules! mcount_mode { () => { ".arm" } }
+
+// `-Z instrument-mcount` calls this after pushing lr. The busy flag is
+// checked before calling into Rust, which is itself instrumented.
+#[cfg(all(feature = "trace-mcount", any(arm, thumb), not(any(target = "thumbv6m-none-eabi", target = "thumbv8m.base-none-eabi"))))]
+core::arch::global_asm!(
+    ".syntax unified",
+    ".pushsection .text.__gnu_mcount_nc,\"ax\",%progbits",
+    ".global __gnu_mcount_nc",
+    ".type __gnu_mcount_nc,%function",
+    mcount_mode!(),
+    "__gnu_mcount_nc:",
+    "push {{r0, r1, r2, r3, lr}}",
+    "ldr r2, ={busy}",
+    "ldrb r3, [r2]",
+    "cmp r3, #0",
+    "bne 1f",
+    "movs r3, #1",
+    "strb r3, [r2]",
+    "mov r0, lr",
+    "ldr r1, [sp, #20]",
+    "bl {record}",
+    "ldr r2, ={busy}",
+    "movs r3, #0",
+    "strb r3, [r2]",
+    "1:",
+    "pop {{r0, r1, r2, r3, r12}}",
+    "pop {{lr}}",
+    "bx r12",
+    ".popsection",
+    busy = sym BUSY,
+    record = sym mcount_record,
+);
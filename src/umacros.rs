@@ -7,8 +7,9 @@ macro_rules! uprint_fmt {
                 ufmt_write::uWrite,
                 ufmt,
             };
+            let mut writer = $crate::print::SinkWriter($crate::print::sink());
             // failures aren't interesting to us
-            let _ = $crate::_export::ufmt::uwrite!(&$crate::LOGGER, $($tt)*);
+            let _ = $crate::_export::ufmt::uwrite!(writer, $($tt)*);
         }
     };
 }
@@ -22,56 +23,32 @@ macro_rules! uprintln_fmt {
                 ufmt_write::uWrite,
                 ufmt,
             };
+            let mut writer = $crate::print::SinkWriter($crate::print::sink());
             // failures aren't interesting to us
-            let _ = $crate::_export::ufmt::uwriteln!(&$crate::LOGGER, $($tt)*);
+            let _ = $crate::_export::ufmt::uwriteln!(writer, $($tt)*);
         }
     };
 }
 
 #[doc(hidden)]
-#[cfg(feature = "enable-logger")]
-#[macro_export]
-macro_rules! uprint_str {
-    ($str:expr) => {
-        {
-            let _ = $crate::_export::ufmt_write::uWrite::write_str(&mut &$crate::LOGGER, $str);
-        }
-    };
-}
-
-#[doc(hidden)]
-#[cfg(feature = "enable-logger")]
-#[macro_export]
-macro_rules! uprintln_str {
-    ($str:expr) => {
-        {
-            let _ = $crate::_export::ufmt_write::uWrite::write_str(&mut &$crate::LOGGER, $str);
-            let _ = $crate::_export::ufmt_write::uWrite::write_str(&mut &$crate::LOGGER, "\n");
-        }
-    };
-}
-
-#[doc(hidden)]
-#[cfg(not(feature = "enable-logger"))]
 #[macro_export]
 macro_rules! uprint_str {
     ($str:literal) => {
-        $crate::print_cstr($crate::_export::cstr!($str).into())
+        $crate::print_str!($str)
     };
     ($str:expr) => {
-        $crate::uprint_fmt!("{}", $str)
+        $crate::print_str!($str)
     };
 }
 
 #[doc(hidden)]
-#[cfg(not(feature = "enable-logger"))]
 #[macro_export]
 macro_rules! uprintln_str {
     ($str:literal) => {
-        $crate::print_cstr($crate::_export::cstr!(concat!($str, "\n")).into())
+        $crate::println_str!($str)
     };
     ($str:expr) => {
-        $crate::uprintln_fmt!("{}", $str)
+        $crate::println_fmt!("{}", $str)
     };
 }
 
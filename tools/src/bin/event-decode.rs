@@ -0,0 +1,233 @@
+//! Converts a `semihosting::event` log to JSON lines
+//!
+//! ```text
+//! event-decode events.bin
+//! ```
+//!
+//! Each record becomes `{"id":3,"temperature":21.5,...}`, with byte strings
+//! written as hex.
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt::Write;
+use std::{env, fs, process};
+use semihosting::event::{HEADER_LEN, MAGIC, VERSION};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Reads CBOR items from a single record
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err("record ends mid item".into())
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn peek_break(&self) -> bool {
+        self.bytes.first() == Some(&0xff)
+    }
+
+    /// Returns the major type and argument, or `None` for indefinite lengths
+    fn head(&mut self) -> Result<(u8, Option<u64>)> {
+        let initial = self.take(1)?[0];
+        let value = match initial & 0x1f {
+            info @ 0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into()?) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into()?) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into()?),
+            31 => return Ok((initial >> 5, None)),
+            info => return Err(format!("reserved additional info {}", info).into()),
+        };
+        Ok((initial >> 5, Some(value)))
+    }
+
+    fn length(&mut self, len: Option<u64>) -> Result<usize> {
+        match len {
+            Some(len) => Ok(len.try_into()?),
+            None => Err("indefinite length strings are not supported".into()),
+        }
+    }
+
+    /// Decodes an item as a map key, which must be a text string
+    fn key(&mut self) -> Result<String> {
+        match self.head()? {
+            (3, len) => {
+                let len = self.length(len)?;
+                Ok(std::str::from_utf8(self.take(len)?)?.to_owned())
+            },
+            (major, _) => Err(format!("unsupported map key of major type {}", major).into()),
+        }
+    }
+
+    fn value(&mut self, out: &mut String) -> Result<()> {
+        let initial = *self.bytes.first().ok_or("record ends mid item")?;
+        match initial {
+            0xf4 => out.push_str("false"),
+            0xf5 => out.push_str("true"),
+            0xf6 | 0xf7 => out.push_str("null"),
+            0xfa => {
+                self.take(1)?;
+                let value = f32::from_bits(u32::from_be_bytes(self.take(4)?.try_into()?));
+                float(out, value as f64);
+            },
+            0xfb => {
+                self.take(1)?;
+                let value = f64::from_bits(u64::from_be_bytes(self.take(8)?.try_into()?));
+                float(out, value);
+            },
+            _ => match self.head()? {
+                (0, Some(value)) => write!(out, "{}", value)?,
+                (1, Some(value)) => write!(out, "{}", -1 - value as i128)?,
+                (2, len) => {
+                    let len = self.length(len)?;
+                    out.push('"');
+                    for byte in self.take(len)? {
+                        write!(out, "{:02x}", byte)?;
+                    }
+                    out.push('"');
+                },
+                (3, len) => {
+                    let len = self.length(len)?;
+                    string(out, std::str::from_utf8(self.take(len)?)?);
+                },
+                (4, len) => {
+                    out.push('[');
+                    let mut i = 0;
+                    while len.map_or(!self.peek_break(), |len| i < len) {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        self.value(out)?;
+                        i += 1;
+                    }
+                    if len.is_none() {
+                        self.take(1)?;
+                    }
+                    out.push(']');
+                },
+                (5, len) => {
+                    out.push('{');
+                    self.fields(out, len, true)?;
+                    out.push('}');
+                },
+                // tags are passed through as their content
+                (6, Some(_)) => self.value(out)?,
+                (major, _) => return Err(format!("unsupported item of major type {}", major).into()),
+            },
+        }
+        Ok(())
+    }
+
+    /// Writes map entries as JSON members, without the surrounding braces
+    fn fields(&mut self, out: &mut String, len: Option<u64>, mut first: bool) -> Result<()> {
+        let mut i = 0;
+        while len.map_or(!self.peek_break(), |len| i < len) {
+            if !first {
+                out.push(',');
+            }
+            first = false;
+            string(out, &self.key()?);
+            out.push(':');
+            self.value(out)?;
+            i += 1;
+        }
+        if len.is_none() {
+            self.take(1)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes an `[id, {fields}]` record into a single JSON object
+    fn record(&mut self) -> Result<String> {
+        let mut out = String::from("{\"id\":");
+        if self.head()? != (4, Some(2)) {
+            return Err("record is not an [id, fields] array".into())
+        }
+        self.value(&mut out)?;
+        match self.head()? {
+            (5, len) => self.fields(&mut out, len, false)?,
+            _ => return Err("record fields are not a map".into()),
+        }
+        out.push('}');
+        Ok(out)
+    }
+}
+
+fn float(out: &mut String, value: f64) {
+    if value.is_finite() {
+        let _ = write!(out, "{}", value);
+    } else {
+        out.push_str("null");
+    }
+}
+
+fn string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("event-decode: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => return Err("usage: event-decode <events.bin>".into()),
+    };
+
+    let data = fs::read(&path)?;
+    if data.len() < HEADER_LEN || data[..4] != MAGIC {
+        return Err(format!("{} is not an event log", path).into());
+    }
+    if data[4] != VERSION {
+        return Err(format!("unsupported event log version {}", data[4]).into());
+    }
+
+    let mut records = &data[HEADER_LEN..];
+    while !records.is_empty() {
+        if records.len() < 2 {
+            eprintln!("event-decode: ignoring truncated record at end of file");
+            break
+        }
+        let len = u16::from_le_bytes([records[0], records[1]]) as usize;
+        let record = match records.get(2..2 + len) {
+            Some(record) => record,
+            None => {
+                eprintln!("event-decode: ignoring truncated record at end of file");
+                break
+            },
+        };
+        records = &records[2 + len..];
+
+        match (Decoder { bytes: record }).record() {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("event-decode: skipping invalid record: {}", e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Renders a `semihosting::trace` file as a call tree
+//!
+//! ```text
+//! trace-decode trace.bin [symbols.txt]
+//! ```
+//!
+//! Symbols are read from `nm` output, e.g. `arm-none-eabi-nm firmware.elf > symbols.txt`.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::error::Error;
+use std::{env, fs, process};
+use semihosting::trace::{Kind, Record, HEADER_LEN, MAGIC, VERSION};
+
+struct Symbols(BTreeMap<u32, String>);
+
+impl Symbols {
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut symbols = BTreeMap::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let mut fields = line.split_whitespace();
+            if let (Some(addr), Some(_), Some(name)) = (fields.next(), fields.next(), fields.next()) {
+                if let Ok(addr) = u32::from_str_radix(addr, 16) {
+                    symbols.insert(addr & !1, name.to_owned());
+                }
+            }
+        }
+        Ok(Symbols(symbols))
+    }
+
+    fn name(&self, addr: u32) -> String {
+        // thumb addresses have the low bit set
+        let addr = addr & !1;
+        match self.0.range(..=addr).next_back() {
+            Some((&start, name)) if start == addr => name.clone(),
+            Some((&start, name)) => format!("{}+{:#x}", name, addr - start),
+            None => format!("{:#010x}", addr),
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("trace-decode: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => path,
+        None => return Err("usage: trace-decode <trace.bin> [symbols.txt]".into()),
+    };
+    let symbols = match args.next() {
+        Some(path) => Symbols::load(&path)?,
+        None => Symbols(BTreeMap::new()),
+    };
+
+    let data = fs::read(&path)?;
+    if data.len() < HEADER_LEN || data[..4] != MAGIC {
+        return Err(format!("{} is not a trace file", path).into());
+    }
+    if data[4] != VERSION {
+        return Err(format!("unsupported trace version {}", data[4]).into());
+    }
+
+    let records = &data[HEADER_LEN..];
+    if records.len() % Record::LEN != 0 {
+        eprintln!("trace-decode: ignoring truncated record at end of file");
+    }
+
+    // (function, timestamp) of each call still in progress
+    let mut stack: Vec<(u32, u32)> = Vec::new();
+    for bytes in records.chunks_exact(Record::LEN) {
+        let record = match Record::decode(bytes.try_into()?) {
+            Some(record) => record,
+            None => return Err(format!("invalid record kind {}", bytes[0]).into()),
+        };
+        let indent = stack.len() * 2;
+        match record.kind {
+            Kind::Enter => {
+                println!("{:>10} {:indent$}{}", record.timestamp, "", symbols.name(record.function), indent = indent);
+                stack.push((record.function, record.timestamp));
+            },
+            Kind::Exit => {
+                // entries may have been overwritten before they were flushed
+                let start = match stack.iter().rposition(|&(function, _)| function == record.function) {
+                    Some(depth) => {
+                        let start = stack[depth].1;
+                        stack.truncate(depth);
+                        Some(start)
+                    },
+                    None => None,
+                };
+                let indent = stack.len() * 2;
+                match start {
+                    Some(start) => println!("{:>10} {:indent$}<- {} (+{})",
+                        record.timestamp, "", symbols.name(record.function), record.timestamp.wrapping_sub(start), indent = indent
+                    ),
+                    None => println!("{:>10} {:indent$}<- {}", record.timestamp, "", symbols.name(record.function), indent = indent),
+                }
+            },
+            Kind::Mcount => println!("{:>10} {:indent$}{} (from {})",
+                record.timestamp, "", symbols.name(record.function), symbols.name(record.call_site), indent = indent
+            ),
+        }
+    }
+    Ok(())
+}